@@ -37,6 +37,9 @@ in {
     };
 
     settingsModule = types.submodule {
+      # Lets newer config sections (sinks, hooks, ...) pass through untouched.
+      freeformType = tomlFormat.type;
+
       options = {
         interval_ms = mkOption {
           type = types.int;
@@ -116,7 +119,13 @@ in {
             Type = "simple";
             ExecStart = let
               pname = "battery-notifier";
-            in "${flake-pkgs.battery-notifier}/bin/${pname} --config-file=${tomlFormat.generate "${pname}-user-config" cfg.settings}";
+              settingsFile = tomlFormat.generate "${pname}-user-config" cfg.settings;
+              # Fails the build when the generated config has problems.
+              configFile = pkgs.runCommand "${pname}-checked-config" {} ''
+                ${flake-pkgs.battery-notifier}/bin/${pname} --config-file=${settingsFile} check-config --no-fs-checks
+                cp ${settingsFile} $out
+              '';
+            in "${flake-pkgs.battery-notifier}/bin/${pname} --config-file=${configFile}";
             Restart = "on-failure";
           };
 
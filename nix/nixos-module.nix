@@ -29,6 +29,9 @@ in {
     };
 
     settingsModule = types.submodule {
+      # Lets newer config sections (sinks, hooks, ...) pass through untouched.
+      freeformType = tomlFormat.type;
+
       options = {
         interval_ms = mkOption {
           type = types.int;
@@ -69,10 +72,35 @@ in {
       default = null;
       type = types.nullOr settingsModule;
     };
+
+    mode = mkOption {
+      type = types.enum ["user" "system"];
+      default = "user";
+      description = ''
+        `user` runs the daemon in every user session. `system` runs it once as a
+        system service, which reads the batteries and performs the privileged
+        actions, along with an agent in every user session rendering its
+        notifications.
+      '';
+    };
+
+    agentSettings = mkOption {
+      default = null;
+      type = types.nullOr tomlFormat.type;
+      description = "Config of the agents in system mode, only sinks and sounds apply.";
+    };
   };
 
   config = let
     cfg = config.services.battery-notifier;
+    pname = "battery-notifier";
+    bin = "${flake-pkgs.battery-notifier}/bin/${pname}";
+    settingsFile = tomlFormat.generate "${pname}-user-config" cfg.settings;
+    # Fails the build when the generated config has problems.
+    configFile = pkgs.runCommand "${pname}-checked-config" {} ''
+      ${bin} --config-file=${settingsFile} check-config --no-fs-checks
+      cp ${settingsFile} $out
+    '';
   in
     mkIf cfg.enable {
       assertions = mkIf (cfg.settings != null) [
@@ -98,21 +126,46 @@ in {
         }
       ];
 
-      systemd.user.services = {
-        battery-notifier = {
-          description = "A very useful battery notifier for window managers";
-
-          serviceConfig = {
-            Type = "simple";
-            ExecStart = let
-              pname = "battery-notifier";
-              configFile = tomlFormat.generate "${pname}-user-config" cfg.settings;
-            in "${flake-pkgs.battery-notifier}/bin/${pname} --config-file=${configFile}";
-            Restart = "on-failure";
-          };
+      systemd.user.services.battery-notifier = mkIf (cfg.mode == "user") {
+        description = "A very useful battery notifier for window managers";
+
+        serviceConfig = {
+          Type = "simple";
+          ExecStart = "${bin} --config-file=${configFile}";
+          Restart = "on-failure";
+        };
 
-          wantedBy = ["default.target"];
+        wantedBy = ["default.target"];
+      };
+
+      systemd.services.battery-notifier = mkIf (cfg.mode == "system") {
+        description = "A very useful battery notifier for window managers, system service";
+
+        serviceConfig = {
+          Type = "simple";
+          ExecStart = "${bin} --system --config-file=${configFile}";
+          # Holds the events socket the agents connect to.
+          RuntimeDirectory = "battery-notifier";
+          RuntimeDirectoryMode = "0755";
+          Restart = "on-failure";
         };
+
+        wantedBy = ["multi-user.target"];
+      };
+
+      systemd.user.services.battery-notifier-agent = mkIf (cfg.mode == "system") {
+        description = "Notifications of the battery-notifier system service";
+
+        serviceConfig = {
+          Type = "simple";
+          ExecStart =
+            if cfg.agentSettings != null
+            then "${bin} --config-file=${tomlFormat.generate "battery-notifier-agent-config" cfg.agentSettings} agent"
+            else "${bin} agent";
+          Restart = "on-failure";
+        };
+
+        wantedBy = ["default.target"];
       };
     };
 }
@@ -0,0 +1,71 @@
+use log::{info, warn};
+use std::{
+    io::{BufRead, BufReader},
+    os::unix::net::UnixStream,
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    broadcast::Message,
+    config::Config,
+    dry_run::{Action, DryRun},
+    notify::send_sound_notification,
+    sink::Notifiers,
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+// Renders what the system service sends through the sinks and sounds of the
+// user config. It runs until killed, reconnecting whenever the service
+// restarts.
+pub fn run_agent(config: &Config, path: &Path, dry_run: Option<DryRun>) -> ! {
+    let mut notifiers = Notifiers::from_config(config, dry_run.clone());
+    // Only the first failed attempt in a row is logged.
+    let mut reachable = true;
+
+    loop {
+        let stream = match UnixStream::connect(path) {
+            Ok(stream) => stream,
+            Err(error) => {
+                if reachable {
+                    warn!(
+                        "unable to reach the system service at {}, retrying: {}",
+                        path.display(),
+                        error
+                    );
+                }
+
+                reachable = false;
+                thread::sleep(RECONNECT_DELAY);
+                continue;
+            }
+        };
+
+        reachable = true;
+        info!("listening to the system service at {}", path.display());
+
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+
+            match serde_json::from_str::<Message>(&line) {
+                Ok(Message::Notification { event }) => {
+                    notifiers.notify(config.get_event_sinks(&event), &event)
+                }
+                Ok(Message::Dismiss) => notifiers.dismiss(),
+                Ok(Message::Sound { sound }) if config.sound => match &dry_run {
+                    Some(dry_run) => dry_run.print(Action::Sound { sound }),
+                    None => send_sound_notification(sound),
+                },
+                Ok(Message::Sound { .. }) => {}
+                Err(error) => warn!("ignoring an invalid message: {}", error),
+            }
+        }
+
+        warn!("the system service went away, reconnecting");
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
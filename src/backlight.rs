@@ -0,0 +1,208 @@
+use log::{info, warn};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    battery::BatteryNotificationLevel,
+    config::BacklightConfig,
+    dry_run::{Action, DryRun},
+    logind::Logind,
+};
+
+pub const BACKLIGHT_BASE: &str = "/sys/class/backlight";
+
+fn read_value(path: &Path) -> io::Result<u32> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+pub struct Backlight {
+    path: PathBuf,
+    logind: Option<Logind>,
+}
+
+impl Backlight {
+    // Scans the backlight class for a node exposing its brightness. Firmware
+    // interfaces come first, then platform ones and raw registers last, as
+    // the kernel recommends to userspace.
+    fn detect_path(root: &str) -> Option<PathBuf> {
+        let rank = |kind: &str| match kind {
+            "firmware" => 0,
+            "platform" => 1,
+            _ => 2,
+        };
+
+        fs::read_dir(root)
+            .ok()?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.join("brightness").exists() && path.join("max_brightness").exists())
+            .min_by_key(|path| {
+                let kind = fs::read_to_string(path.join("type")).unwrap_or_default();
+                (rank(kind.trim()), path.to_owned())
+            })
+    }
+
+    pub fn detect(root: &str, logind: Option<Logind>) -> Option<Self> {
+        Self::detect_path(root).map(|path| Self { path, logind })
+    }
+
+    pub fn get_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    pub fn get_brightness(&self) -> io::Result<u32> {
+        read_value(&self.path.join("brightness"))
+    }
+
+    // The attribute is usually writable by root only, logind is asked when
+    // the write is refused.
+    pub fn set_brightness(&self, brightness: u32) -> io::Result<()> {
+        match fs::write(self.path.join("brightness"), brightness.to_string()) {
+            Err(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+                let Some(logind) = &self.logind else {
+                    return Err(error);
+                };
+
+                logind
+                    .set_brightness("backlight", &self.get_name(), brightness)
+                    .map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, e))
+            }
+            result => result,
+        }
+    }
+}
+
+// Dims the screen as the battery drains and brings the brightness back once
+// the adapter is plugged in.
+pub struct BacklightDimmer {
+    config: BacklightConfig,
+    backlight: Option<Backlight>,
+    dry_run: Option<DryRun>,
+    // Brightness before dimming and the one set by the daemon, a brightness
+    // changed by hand since then is left alone.
+    original: Option<u32>,
+    applied: Option<u32>,
+}
+
+impl BacklightDimmer {
+    pub fn new(
+        config: &BacklightConfig,
+        root: &str,
+        logind: Option<Logind>,
+        dry_run: Option<DryRun>,
+    ) -> Self {
+        let backlight = config
+            .enabled
+            .then(|| Backlight::detect(root, logind))
+            .flatten();
+
+        match &backlight {
+            Some(backlight) => info!("dimming {} at low levels", backlight.path.display()),
+            None if config.enabled => {
+                warn!("no backlight found under {root}, the screen won't be dimmed")
+            }
+            None => {}
+        }
+
+        Self {
+            config: config.clone(),
+            backlight,
+            dry_run,
+            original: None,
+            applied: None,
+        }
+    }
+
+    // Detects the backlight again for a reloaded config. What was dimmed so
+    // far is still restored on plug.
+    pub fn set_config(&mut self, config: &BacklightConfig, root: &str, logind: Option<Logind>) {
+        let dimmer = Self::new(config, root, logind, self.dry_run.take());
+        *self = Self {
+            original: self.original,
+            applied: self.applied,
+            ..dimmer
+        };
+    }
+
+    fn set_brightness(&self, backlight: &Backlight, brightness: u32) -> bool {
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.print(Action::Brightness { brightness });
+            return true;
+        }
+
+        match backlight.set_brightness(brightness) {
+            Ok(()) => {
+                info!("set the brightness to {}", brightness);
+                true
+            }
+            Err(error) => {
+                warn!("unable to set the brightness to {}: {}", brightness, error);
+                false
+            }
+        }
+    }
+
+    pub fn enter_level(&mut self, level: BatteryNotificationLevel) {
+        let Some(backlight) = &self.backlight else {
+            return;
+        };
+
+        let percent = self.config.get_dim_percent(level).min(100);
+        if percent == 0 {
+            return;
+        }
+
+        let current = match backlight.get_brightness() {
+            Ok(current) => current,
+            Err(error) => {
+                warn!("unable to read the brightness: {}", error);
+                return;
+            }
+        };
+
+        // Dimming is relative to the brightness before the first level so
+        // levels don't compound, and never turns the screen off.
+        let original = match (self.original, self.applied) {
+            (Some(original), Some(applied)) if applied == current => original,
+            _ => current,
+        };
+        let brightness = (original as u64 * (100 - percent) as u64 / 100).max(1) as u32;
+
+        if brightness >= current || !self.set_brightness(backlight, brightness) {
+            return;
+        }
+
+        self.original = Some(original);
+        self.applied = Some(brightness);
+    }
+
+    pub fn plug(&mut self) {
+        let Some(backlight) = &self.backlight else {
+            return;
+        };
+
+        let (Some(original), Some(applied)) = (self.original.take(), self.applied.take()) else {
+            return;
+        };
+
+        if !self.config.restore_on_plug {
+            return;
+        }
+
+        match backlight.get_brightness() {
+            Ok(current) if current == applied => {
+                self.set_brightness(backlight, original);
+            }
+            Ok(_) => info!("the brightness was changed since dimming, keeping it"),
+            Err(error) => warn!("unable to read the brightness: {}", error),
+        }
+    }
+}
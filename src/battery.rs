@@ -1,47 +1,119 @@
-use chrono::Utc;
 use linuxver::version as get_linux_version;
 use log::{info, warn};
-use serde::Deserialize;
-use std::{fmt, fs, io, ops::Index};
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, io, path::Path, time::Duration};
 
-const POWER_SUPPLY_BASE: &str = "/sys/class/power_supply";
+use crate::{
+    charge_control::ChargeThresholds,
+    clock::Clock,
+    simulation::{create_simulation_root, Debug, DebugSettings},
+};
+
+pub const POWER_SUPPLY_BASE: &str = "/sys/class/power_supply";
 
 pub struct PowerSupplyClass {
+    root: String,
     path: String,
+    adapter_paths: Vec<String>,
     debug: Option<Debug>,
 }
 
+// External power source, either a barrel/Mains adapter or a USB(-C) port.
+#[derive(Debug, Clone, Serialize)]
+pub struct Adapter {
+    pub name: String,
+    pub kind: String,
+    pub online: bool,
+    pub usb_type: Option<String>,
+    pub max_watts: Option<f64>,
+}
+
+// Battery of a device powered by the system, such as a wireless mouse or headset.
+#[derive(Debug, Clone)]
+pub struct Peripheral {
+    pub name: String,
+    pub model_name: String,
+    pub capacity: u8,
+    pub status: String,
+}
+
+impl Adapter {
+    pub fn describe(&self) -> String {
+        match &self.usb_type {
+            Some(usb_type) => format!("{} {}", self.kind, usb_type),
+            None => self.kind.to_owned(),
+        }
+    }
+}
+
 impl PowerSupplyClass {
-    pub fn new(debug_file_path: Option<String>) -> PowerSupplyClass {
+    pub fn new(
+        root: Option<String>,
+        debug_settings: Option<DebugSettings>,
+        clock: Clock,
+    ) -> PowerSupplyClass {
         let kernel_version = get_linux_version().expect("must use a Linux kernel");
         if kernel_version.major == 2 && kernel_version.minor < 6 {
             panic!("This program requires Linux 2.6 or higher");
         }
 
-        let path = Self::detect_battery_path().unwrap_or_else(|| {
-            let fallback = format!("{}/BAT0", POWER_SUPPLY_BASE);
-            warn!("no battery node found under {POWER_SUPPLY_BASE}, falling back to {fallback}");
+        let mut debug = debug_settings.map(|settings| Debug::new(settings, clock));
+
+        // Simulations are played through a fake power supply class, so nothing
+        // but the values read differs from a real run. It's always a private
+        // directory, the nodes written there are removed on exit.
+        let root = match (root, &debug) {
+            (root, Some(_)) => {
+                if let Some(root) = root {
+                    warn!("simulations don't read {root}, the power supply root is ignored");
+                }
+
+                create_simulation_root().expect("create simulated power supply class")
+            }
+            (Some(root), None) => root,
+            (None, None) => POWER_SUPPLY_BASE.to_string(),
+        };
+
+        if let Some(debug) = debug.as_mut() {
+            debug
+                .write_tree(&root)
+                .expect("write simulated power supply class");
+            info!("simulating battery states under {root}");
+        }
+
+        let path = Self::detect_battery_path(&root).unwrap_or_else(|| {
+            let fallback = format!("{}/BAT0", root);
+            warn!("no battery node found under {root}, falling back to {fallback}");
             fallback
         });
         info!("using battery node at {path}");
 
+        let adapter_paths = Self::detect_adapter_paths(&root);
+        for adapter_path in &adapter_paths {
+            info!("using adapter node at {adapter_path}");
+        }
+
         PowerSupplyClass {
+            root,
             path,
-            debug: debug_file_path.map(|p| {
-                let settings = DebugSettings::parse(p);
-                Debug::new(settings)
-            }),
+            adapter_paths,
+            debug,
         }
     }
 
     // Scans the power supply class for the first node reporting type "Battery"
     // and exposing a capacity file, instead of guessing BAT0/BAT1 from the OS.
-    fn detect_battery_path() -> Option<String> {
-        fs::read_dir(POWER_SUPPLY_BASE).ok()?.flatten().find_map(|entry| {
+    // Peripheral batteries share the type and are skipped by their scope.
+    fn detect_battery_path(root: &str) -> Option<String> {
+        fs::read_dir(root).ok()?.flatten().find_map(|entry| {
             let path = entry.path();
             let kind = fs::read_to_string(path.join("type")).ok()?;
+            let scope = fs::read_to_string(path.join("scope")).unwrap_or_default();
 
-            if kind.trim() == "Battery" && path.join("capacity").exists() {
+            if kind.trim() == "Battery"
+                && scope.trim() != "Device"
+                && path.join("capacity").exists()
+            {
                 Some(path.to_string_lossy().into_owned())
             } else {
                 None
@@ -49,19 +121,115 @@ impl PowerSupplyClass {
         })
     }
 
-    pub fn get_capacity(&mut self) -> io::Result<u8> {
-        if self.debug.is_some() {
-            let debug = self.debug.as_mut().unwrap();
-            let now = Utc::now().time();
+    // Mains and USB nodes expose an `online` attribute telling whether power is
+    // actually being supplied, which is more reliable than the battery status.
+    fn detect_adapter_paths(root: &str) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(root) else {
+            return Vec::new();
+        };
+
+        entries
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                let kind = fs::read_to_string(path.join("type")).ok()?;
+
+                if matches!(kind.trim(), "Mains" | "USB") && path.join("online").exists() {
+                    Some(path.to_string_lossy().into_owned())
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn get_adapters(&self) -> Vec<Adapter> {
+        self.adapter_paths
+            .iter()
+            .filter_map(|path| {
+                let read = |name: &str| -> Option<String> {
+                    fs::read_to_string(format!("{}/{}", path, name))
+                        .ok()
+                        .map(|v| v.trim().to_string())
+                };
+                let read_number = |name: &str| -> Option<f64> { read(name)?.parse::<f64>().ok() };
 
-            if debug.should_move_to_next_state(now) {
-                debug.last_update_at = now;
-                debug.next_state();
-            };
+                // The kernel lists every supported type and brackets the negotiated
+                // one, e.g. "C [PD] PD_PPS".
+                let usb_type = read("usb_type").map(|types| {
+                    types
+                        .split_whitespace()
+                        .find_map(|t| t.strip_prefix('[')?.strip_suffix(']'))
+                        .unwrap_or(types.as_str())
+                        .to_string()
+                });
 
-            return Ok(debug.get_current_state().capacity);
+                // voltage_max and current_max are expressed in µV and µA.
+                let max_watts = match (read_number("voltage_max"), read_number("current_max")) {
+                    (Some(voltage), Some(current)) if voltage > 0.0 && current > 0.0 => {
+                        Some(voltage * current / 1e12)
+                    }
+                    _ => None,
+                };
+
+                Some(Adapter {
+                    name: path.rsplit('/').next().unwrap_or(path).to_string(),
+                    kind: read("type")?,
+                    online: read("online")? == "1",
+                    usb_type,
+                    max_watts,
+                })
+            })
+            .collect()
+    }
+
+    // Whether any adapter is supplying power, None when the machine doesn't
+    // expose adapter nodes and the battery status has to be trusted instead.
+    pub fn get_online_adapter(&self) -> Option<Option<Adapter>> {
+        if self.adapter_paths.is_empty() {
+            return None;
         }
 
+        Some(self.get_adapters().into_iter().find(|a| a.online))
+    }
+
+    // Peripherals connect and disconnect at any time, so the class is scanned on
+    // every call. Their nodes are told apart from the system battery by
+    // `scope == Device`.
+    pub fn get_peripherals(&self) -> Vec<Peripheral> {
+        let Ok(entries) = fs::read_dir(&self.root) else {
+            return Vec::new();
+        };
+
+        entries
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                let read = |name: &str| -> Option<String> {
+                    fs::read_to_string(path.join(name))
+                        .ok()
+                        .map(|v| v.trim().to_string())
+                };
+
+                if read("scope")? != "Device" {
+                    return None;
+                }
+
+                let name = entry.file_name().to_string_lossy().into_owned();
+
+                Some(Peripheral {
+                    model_name: read("model_name")
+                        .filter(|m| !m.is_empty())
+                        .unwrap_or(name.to_owned()),
+                    capacity: read("capacity")?.parse::<u8>().ok()?,
+                    status: read("status").unwrap_or("Unknown".to_string()),
+                    name,
+                })
+            })
+            .collect()
+    }
+
+    pub fn get_capacity(&self) -> io::Result<u8> {
         let raw_capacity = fs::read_to_string(self.get_capacity_path())?.replace("\n", "");
 
         raw_capacity.parse::<u8>().map_err(|e| {
@@ -72,20 +240,65 @@ impl PowerSupplyClass {
         })
     }
 
-    pub fn get_status(&mut self) -> io::Result<String> {
-        if self.debug.is_some() {
-            let debug = self.debug.as_mut().unwrap();
-            let now = Utc::now().time();
+    pub fn get_status(&self) -> io::Result<String> {
+        Ok(fs::read_to_string(self.get_status_path())?.replace("\n", ""))
+    }
+
+    // Estimates how long the battery lasts at the current draw. Most drivers report
+    // energy_now/power_now (µWh, µW), others only charge_now/current_now (µAh, µA),
+    // and a few sign the rate, so the absolute value is used.
+    pub fn get_time_remaining(&self) -> Option<Duration> {
+        let read = |name: &str| self.read_number(name);
 
-            if debug.should_move_to_next_state(now) {
-                debug.last_update_at = now;
-                debug.next_state();
-            };
+        let (stored, rate) = match (read("energy_now"), read("power_now")) {
+            (Some(energy), Some(power)) => (energy, power),
+            _ => (read("charge_now")?, read("current_now")?),
+        };
 
-            return Ok(debug.get_current_state().status);
+        if rate.abs() < f64::EPSILON {
+            return None;
         }
 
-        Ok(fs::read_to_string(self.get_status_path())?.replace("\n", ""))
+        Some(Duration::from_secs_f64(stored / rate.abs() * 3600.0))
+    }
+
+    pub fn get_power_now(&self) -> Option<u64> {
+        self.read_number("power_now").map(|p| p.abs() as u64)
+    }
+
+    pub fn get_energy_full(&self) -> Option<u64> {
+        self.read_number("energy_full").map(|e| e as u64)
+    }
+
+    fn read_number(&self, name: &str) -> Option<f64> {
+        let raw = fs::read_to_string(format!("{}/{}", self.path, name)).ok()?;
+        raw.trim().parse::<f64>().ok()
+    }
+
+    // Charge thresholds configured in the firmware, if the driver exposes them.
+    pub fn get_charge_thresholds(&self) -> ChargeThresholds {
+        ChargeThresholds::read(Path::new(&self.path))
+    }
+
+    pub fn get_battery_path(&self) -> &str {
+        &self.path
+    }
+
+    // Advances the simulation, if any, and writes its current state to the
+    // simulated power supply class. It must be called before every read.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        match self.debug.as_mut() {
+            Some(debug) => {
+                debug.tick();
+                debug.write_tree(&self.root)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Whether a simulation that doesn't loop already played every state.
+    pub fn is_simulation_over(&self) -> bool {
+        self.debug.as_ref().is_some_and(|d| d.is_finished())
     }
 
     fn get_capacity_path(&self) -> String {
@@ -97,7 +310,16 @@ impl PowerSupplyClass {
     }
 }
 
-#[derive(PartialEq, Clone, Copy)]
+impl Drop for PowerSupplyClass {
+    fn drop(&mut self) {
+        if let Some(debug) = self.debug.as_mut() {
+            debug.remove_tree()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum BatteryNotificationLevel {
     NoConflict,
     Reminder,
@@ -106,6 +328,18 @@ pub enum BatteryNotificationLevel {
     Charging,
 }
 
+impl BatteryNotificationLevel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BatteryNotificationLevel::NoConflict => "no_conflict",
+            BatteryNotificationLevel::Reminder => "reminder",
+            BatteryNotificationLevel::Warn => "warn",
+            BatteryNotificationLevel::Threat => "threat",
+            BatteryNotificationLevel::Charging => "charging",
+        }
+    }
+}
+
 impl fmt::Display for BatteryNotificationLevel {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -117,59 +351,3 @@ impl fmt::Display for BatteryNotificationLevel {
         }
     }
 }
-
-#[derive(Clone, Deserialize)]
-struct DebugState {
-    status: String,
-    capacity: u8,
-}
-
-#[derive(Clone, Deserialize)]
-pub struct DebugSettings {
-    states: Vec<DebugState>,
-    seconds_between: i64,
-}
-
-impl DebugSettings {
-    pub fn parse(debug_file_path: String) -> Self {
-        let content = fs::read_to_string(debug_file_path).expect("read file path");
-        let options: DebugSettings = serde_yaml::from_str(&content).expect("parse debug file");
-
-        options
-    }
-}
-
-struct Debug {
-    settings: DebugSettings,
-    current_state: usize,
-    last_update_at: chrono::NaiveTime,
-}
-
-impl Debug {
-    fn new(settings: DebugSettings) -> Self {
-        Self {
-            settings,
-            current_state: 0,
-            last_update_at: Utc::now().time(),
-        }
-    }
-
-    fn get_current_state(&self) -> DebugState {
-        self.settings.states.index(self.current_state).clone()
-    }
-
-    fn should_move_to_next_state(&self, now: chrono::NaiveTime) -> bool {
-        let diff = now - self.last_update_at;
-        diff.num_seconds() >= self.settings.seconds_between
-    }
-
-    fn next_state(&mut self) {
-        let mut next_state = self.current_state + 1;
-
-        if next_state >= self.settings.states.len() {
-            next_state = 0
-        }
-
-        self.current_state = next_state
-    }
-}
@@ -0,0 +1,175 @@
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Write},
+    mem,
+    os::{
+        fd::AsRawFd,
+        unix::{
+            fs::PermissionsExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{event::Event, notify::Sound, xdg::get_system_runtime_dir};
+
+// A user has an agent in each of their sessions, a handful at most.
+const MAX_AGENTS_PER_USER: usize = 4;
+
+pub fn get_events_socket_path() -> PathBuf {
+    get_system_runtime_dir().join("events.sock")
+}
+
+// What the system service leaves to the agents of the user sessions, one
+// JSON line each.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    Notification { event: Event },
+    Dismiss,
+    Sound { sound: Sound },
+}
+
+struct Agent {
+    uid: u32,
+    stream: UnixStream,
+}
+
+#[derive(Default)]
+struct State {
+    agents: Vec<Agent>,
+    // Last notification about the system battery, sent to agents connecting
+    // later such as on login, until it's dismissed.
+    current: Option<String>,
+}
+
+// Sends messages to every agent connected to the events socket. Agents only
+// listen, anyone may connect since the events tell nothing the power supply
+// class doesn't. Every user gets a few connections, so no one can make the
+// service hold an unbounded number of them, nor take the slots of the others.
+#[derive(Clone)]
+pub struct Broadcaster {
+    state: Arc<Mutex<State>>,
+}
+
+impl Broadcaster {
+    pub fn start(path: &Path) -> io::Result<Self> {
+        // Left behind by an instance that didn't exit cleanly.
+        let _ = fs::remove_file(path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+
+        let broadcaster = Broadcaster {
+            state: Arc::new(Mutex::new(State::default())),
+        };
+
+        let state = broadcaster.state.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let uid = match get_peer_uid(&stream) {
+                    Ok(uid) => uid,
+                    Err(error) => {
+                        warn!(
+                            "unable to tell who connected to the events socket: {}",
+                            error
+                        );
+                        continue;
+                    }
+                };
+
+                // A stuck agent must never hold the battery checks back.
+                let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+
+                let mut state = state.lock().expect("lock broadcast state");
+                // Agents that went away since the last message don't count.
+                state.agents.retain(|agent| !has_hung_up(&agent.stream));
+                let connected = state.agents.iter().filter(|a| a.uid == uid).count();
+                if connected >= MAX_AGENTS_PER_USER {
+                    warn!(
+                        "refusing an agent of uid {}, {} are connected already",
+                        uid, connected
+                    );
+                    continue;
+                }
+                info!("an agent of uid {} connected", uid);
+
+                if let Some(line) = &state.current {
+                    if writeln!(stream, "{}", line).is_err() {
+                        continue;
+                    }
+                }
+                state.agents.push(Agent { uid, stream });
+            }
+        });
+
+        Ok(broadcaster)
+    }
+
+    pub fn send(&self, message: &Message) {
+        let line = serde_json::to_string(message).expect("serialize message");
+        let mut state = self.state.lock().expect("lock broadcast state");
+
+        match message {
+            Message::Notification { event } if event.device.is_none() => {
+                state.current = Some(line.to_owned())
+            }
+            Message::Dismiss => state.current = None,
+            _ => {}
+        }
+
+        // Agents that went away are forgotten.
+        state.agents.retain_mut(|agent| {
+            let sent = writeln!(agent.stream, "{}", line);
+            if let Err(error) = &sent {
+                debug!("dropping an agent: {}", error);
+            }
+
+            sent.is_ok()
+        });
+    }
+}
+
+fn get_peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    match result {
+        0 => Ok(cred.uid),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+// Agents never write, their socket only reads as closed once they hang up.
+fn has_hung_up(stream: &UnixStream) -> bool {
+    let mut byte = 0u8;
+    let read = unsafe {
+        libc::recv(
+            stream.as_raw_fd(),
+            &mut byte as *mut u8 as *mut libc::c_void,
+            1,
+            libc::MSG_PEEK | libc::MSG_DONTWAIT,
+        )
+    };
+
+    read == 0 || (read < 0 && io::Error::last_os_error().kind() != io::ErrorKind::WouldBlock)
+}
@@ -0,0 +1,291 @@
+use log::warn;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{mpsc::Sender, Arc, Mutex},
+    time::{Duration, Instant},
+};
+use zbus::{
+    blocking::{Connection, ConnectionBuilder},
+    dbus_interface, fdo,
+    zvariant::Value as Variant,
+    SignalContext,
+};
+
+use crate::{
+    battery::BatteryNotificationLevel,
+    config::Config,
+    layers::{LayeredConfig, Source},
+    sleep::SleepEvent,
+};
+
+pub const SERVICE_NAME: &str = "io.github.luisnquin.BatteryNotifier";
+const OBJECT_PATH: &str = "/io/github/luisnquin/BatteryNotifier";
+
+// What the loop is asked to do on its next iteration.
+pub enum ServiceRequest {
+    Reload(Box<Config>),
+    TestNotification,
+}
+
+struct State {
+    capacity: u8,
+    status: String,
+    level: BatteryNotificationLevel,
+    time_remaining: Option<Duration>,
+    snoozed_until: Option<Instant>,
+    // Last value told to the bus, a snooze runs out without anyone calling.
+    snoozed: bool,
+    muted: bool,
+    requests: Vec<ServiceRequest>,
+}
+
+impl State {
+    fn is_snoozed(&self) -> bool {
+        self.snoozed_until
+            .is_some_and(|until| Instant::now() < until)
+    }
+}
+
+struct Interface {
+    state: Arc<Mutex<State>>,
+    // Layers `Reload` reads again, the same ones as at startup.
+    config_file: Option<String>,
+    overrides: Vec<(String, Value, Source)>,
+    // Wakes the loop up so requests don't wait for the next battery check.
+    waker: Sender<SleepEvent>,
+}
+
+impl Interface {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("lock service state")
+    }
+
+    fn request(&self, request: ServiceRequest) {
+        self.lock().requests.push(request);
+        let _ = self.waker.send(SleepEvent::Wake);
+    }
+}
+
+#[dbus_interface(name = "io.github.luisnquin.BatteryNotifier")]
+impl Interface {
+    /// Battery capacity at the last check, in percent.
+    #[dbus_interface(property)]
+    fn capacity(&self) -> u8 {
+        self.lock().capacity
+    }
+
+    /// Battery status as the kernel reports it.
+    #[dbus_interface(property)]
+    fn status(&self) -> String {
+        self.lock().status.to_owned()
+    }
+
+    /// Last level notified, `no_conflict` above the reminder and `charging` on the adapter.
+    #[dbus_interface(property)]
+    fn level(&self) -> String {
+        self.lock().level.name().to_string()
+    }
+
+    /// Seconds until the battery runs out, 0 when unknown.
+    #[dbus_interface(property)]
+    fn time_remaining(&self) -> u64 {
+        self.lock().time_remaining.map_or(0, |time| time.as_secs())
+    }
+
+    #[dbus_interface(property)]
+    fn snoozed(&self) -> bool {
+        self.lock().snoozed
+    }
+
+    #[dbus_interface(property)]
+    fn muted(&self) -> bool {
+        self.lock().muted
+    }
+
+    /// Holds low battery notifications back for `seconds`, 0 to stop snoozing.
+    async fn snooze(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        seconds: u32,
+    ) -> fdo::Result<()> {
+        {
+            let mut state = self.lock();
+            state.snoozed_until =
+                (seconds > 0).then(|| Instant::now() + Duration::from_secs(seconds.into()));
+            state.snoozed = state.is_snoozed();
+        }
+
+        self.snoozed_changed(&ctxt).await?;
+        Ok(())
+    }
+
+    /// Turns sounds off until called again with false.
+    async fn mute(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        muted: bool,
+    ) -> fdo::Result<()> {
+        self.lock().muted = muted;
+
+        self.muted_changed(&ctxt).await?;
+        Ok(())
+    }
+
+    /// Reads the config again, refused when a layer can't be parsed or it has problems.
+    fn reload(&self) -> fdo::Result<()> {
+        let layers = LayeredConfig::load(self.config_file.as_deref(), self.overrides.clone());
+        if !layers.errors.is_empty() {
+            return Err(fdo::Error::Failed(layers.errors.join(", ")));
+        }
+
+        let config = layers.config;
+        let problems = config.check();
+        if !problems.is_empty() {
+            let messages: Vec<&str> = problems.iter().map(|p| p.message.as_str()).collect();
+            return Err(fdo::Error::Failed(messages.join(", ")));
+        }
+
+        self.request(ServiceRequest::Reload(Box::new(config)));
+        Ok(())
+    }
+
+    /// Sends a notification through the configured sinks.
+    fn test_notification(&self) {
+        self.request(ServiceRequest::TestNotification)
+    }
+
+    /// Sent when the loop moves from the level `previous` to `level`.
+    #[dbus_interface(signal)]
+    async fn level_entered(
+        ctxt: &SignalContext<'_>,
+        level: &str,
+        previous: &str,
+        capacity: u8,
+    ) -> zbus::Result<()>;
+}
+
+// Owns the well-known name on the session bus so desktop widgets can follow
+// the battery and act on the loop. Properties mirror what the loop knows
+// after every check.
+pub struct BusService {
+    connection: Connection,
+    state: Arc<Mutex<State>>,
+}
+
+impl BusService {
+    pub fn start(
+        config_file: Option<String>,
+        overrides: Vec<(String, Value, Source)>,
+        waker: Sender<SleepEvent>,
+    ) -> zbus::Result<Self> {
+        let state = Arc::new(Mutex::new(State {
+            capacity: 0,
+            status: "Unknown".to_string(),
+            level: BatteryNotificationLevel::NoConflict,
+            time_remaining: None,
+            snoozed_until: None,
+            snoozed: false,
+            muted: false,
+            requests: Vec::new(),
+        }));
+
+        let interface = Interface {
+            state: state.clone(),
+            config_file,
+            overrides,
+            waker,
+        };
+
+        let connection = ConnectionBuilder::session()?
+            .name(SERVICE_NAME)?
+            .serve_at(OBJECT_PATH, interface)?
+            .build()?;
+
+        Ok(Self { connection, state })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("lock service state")
+    }
+
+    pub fn take_requests(&self) -> Vec<ServiceRequest> {
+        mem::take(&mut self.lock().requests)
+    }
+
+    pub fn is_snoozed(&self) -> bool {
+        self.lock().is_snoozed()
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.lock().muted
+    }
+
+    // Updates the properties, telling the bus about the ones that changed and
+    // about level transitions.
+    pub fn publish(
+        &self,
+        capacity: u8,
+        status: &str,
+        level: BatteryNotificationLevel,
+        time_remaining: Option<Duration>,
+    ) {
+        let mut changed: HashMap<&str, Variant> = HashMap::new();
+        let previous;
+        {
+            let mut state = self.lock();
+
+            if state.capacity != capacity {
+                state.capacity = capacity;
+                changed.insert("Capacity", capacity.into());
+            }
+            if state.status != status {
+                state.status = status.to_owned();
+                changed.insert("Status", status.to_owned().into());
+            }
+            if state.level != level {
+                changed.insert("Level", level.name().into());
+            }
+            if state.time_remaining != time_remaining {
+                state.time_remaining = time_remaining;
+                changed.insert(
+                    "TimeRemaining",
+                    time_remaining.map_or(0, |time| time.as_secs()).into(),
+                );
+            }
+
+            let snoozed = state.is_snoozed();
+            if state.snoozed != snoozed {
+                state.snoozed = snoozed;
+                changed.insert("Snoozed", snoozed.into());
+            }
+
+            previous = mem::replace(&mut state.level, level);
+        }
+
+        let mut result = Ok(());
+        if !changed.is_empty() {
+            result = self.connection.emit_signal(
+                None::<&str>,
+                OBJECT_PATH,
+                "org.freedesktop.DBus.Properties",
+                "PropertiesChanged",
+                &(SERVICE_NAME, changed, Vec::<&str>::new()),
+            );
+        }
+        if result.is_ok() && previous != level {
+            result = self.connection.emit_signal(
+                None::<&str>,
+                OBJECT_PATH,
+                SERVICE_NAME,
+                "LevelEntered",
+                &(level.name(), previous.name(), capacity),
+            );
+        }
+
+        if let Err(error) = result {
+            warn!("unable to tell the bus about the battery: {}", error);
+        }
+    }
+}
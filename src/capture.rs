@@ -0,0 +1,67 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+};
+
+// One reading of the system battery, captures are made of one JSON object per
+// line so they can be appended to, trimmed and diffed with the usual tools.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    // Milliseconds since the capture started.
+    pub at_ms: u64,
+    pub capacity: u8,
+    pub status: String,
+    // µW and µWh, as reported by the kernel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power_now: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub energy_full: Option<u64>,
+    // Missing when the machine exposes no adapter node.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adapter_online: Option<bool>,
+}
+
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    // Every sample is flushed right away, a capture must survive the daemon
+    // being killed.
+    pub fn record(&mut self, sample: &Sample) {
+        let result = serde_json::to_string(sample)
+            .map_err(io::Error::from)
+            .and_then(|line| writeln!(self.writer, "{}", line))
+            .and_then(|_| self.writer.flush());
+
+        if let Err(error) = result {
+            warn!("unable to record battery sample: {}", error)
+        }
+    }
+}
+
+pub fn load_capture(path: &str) -> io::Result<Vec<Sample>> {
+    let content = fs::read_to_string(path)?;
+
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: invalid sample: {}", path, i + 1, e),
+                )
+            })
+        })
+        .collect()
+}
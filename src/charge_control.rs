@@ -0,0 +1,180 @@
+use std::{env, fmt, fs, io, path::Path, process::Command};
+
+use crate::battery::POWER_SUPPLY_BASE;
+
+const START_THRESHOLD: &str = "charge_control_start_threshold";
+const END_THRESHOLD: &str = "charge_control_end_threshold";
+
+// Capacities charging starts again below and stops at, None when the driver
+// doesn't expose the attribute or when left alone.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChargeThresholds {
+    pub start: Option<u8>,
+    pub end: Option<u8>,
+}
+
+impl ChargeThresholds {
+    pub fn read(battery: &Path) -> Self {
+        let read = |name: &str| {
+            fs::read_to_string(battery.join(name))
+                .ok()?
+                .trim()
+                .parse::<u8>()
+                .ok()
+        };
+
+        Self {
+            // Some drivers report 0 for a start threshold that isn't used.
+            start: read(START_THRESHOLD).filter(|start| *start > 0),
+            end: read(END_THRESHOLD),
+        }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        for threshold in [self.start, self.end].into_iter().flatten() {
+            if threshold == 0 || threshold > 100 {
+                return Err(format!(
+                    "charge thresholds must be between 1 and 100, got {}",
+                    threshold
+                ));
+            }
+        }
+
+        match (self.start, self.end) {
+            (Some(start), Some(end)) if start >= end => Err(format!(
+                "the start threshold ({}) must be lower than the end threshold ({})",
+                start, end
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Whether `current` already has every threshold set here.
+    pub fn is_applied(&self, current: &ChargeThresholds) -> bool {
+        self.start.is_none_or(|start| current.start == Some(start))
+            && self.end.is_none_or(|end| current.end == Some(end))
+    }
+}
+
+impl fmt::Display for ChargeThresholds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) => {
+                write!(f, "charging starts below {start}% and stops at {end}%")
+            }
+            (Some(start), None) => write!(f, "charging starts below {start}%"),
+            (None, Some(end)) => write!(f, "charging stops at {end}%"),
+            (None, None) => write!(f, "no charge thresholds"),
+        }
+    }
+}
+
+// Drivers refuse a start threshold above the end one, so the end is written
+// first when the start goes past the current end.
+fn write_thresholds(battery: &Path, thresholds: ChargeThresholds) -> io::Result<()> {
+    let write = |name: &str, value: Option<u8>| {
+        let Some(value) = value else {
+            return Ok(());
+        };
+
+        let path = battery.join(name);
+        if !path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("the battery doesn't expose {}", name),
+            ));
+        }
+
+        fs::write(path, value.to_string())
+    };
+
+    let current = ChargeThresholds::read(battery);
+    let end_first = matches!(
+        (thresholds.start, current.end),
+        (Some(start), Some(end)) if start >= end
+    );
+
+    if end_first {
+        write(END_THRESHOLD, thresholds.end)?;
+        write(START_THRESHOLD, thresholds.start)
+    } else {
+        write(START_THRESHOLD, thresholds.start)?;
+        write(END_THRESHOLD, thresholds.end)
+    }
+}
+
+// The attributes are usually writable by root only, `helper` (pkexec by
+// default) runs the hidden `charge-control-helper` subcommand of this same
+// executable when the write is refused.
+fn run_helper(helper: &str, battery: &Path, thresholds: ChargeThresholds) -> Result<(), String> {
+    let mut words = helper.split_whitespace();
+    let Some(program) = words.next() else {
+        return Err("no helper is configured to write the charge thresholds as root".to_string());
+    };
+
+    let name = battery
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("invalid battery path {}", battery.display()))?;
+    let exe = env::current_exe().map_err(|e| format!("unable to locate the executable: {}", e))?;
+
+    let mut command = Command::new(program);
+    command.args(words).arg(exe).arg("charge-control-helper");
+    if let Some(start) = thresholds.start {
+        command.arg("--start").arg(start.to_string());
+    }
+    if let Some(end) = thresholds.end {
+        command.arg("--end").arg(end.to_string());
+    }
+    command.arg(name);
+
+    match command.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{} failed ({})", program, status)),
+        Err(error) => Err(format!("unable to run {}: {}", program, error)),
+    }
+}
+
+pub fn set_thresholds(
+    battery: &Path,
+    thresholds: ChargeThresholds,
+    helper: &str,
+) -> Result<(), String> {
+    thresholds.check()?;
+
+    match write_thresholds(battery, thresholds) {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+            run_helper(helper, battery, thresholds)
+        }
+        Err(error) => Err(format!("unable to write the charge thresholds: {}", error)),
+    }
+}
+
+// Runs `charge-control-helper` and returns the exit code. It runs as root, so
+// nothing but a battery of the real power supply class is written.
+pub fn run_charge_control_helper(name: &str, thresholds: ChargeThresholds) -> i32 {
+    let fail = |message: String| {
+        eprintln!("error: {}", message);
+        1
+    };
+
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        return fail(format!("invalid battery name '{}'", name));
+    }
+
+    let battery = Path::new(POWER_SUPPLY_BASE).join(name);
+    let kind = fs::read_to_string(battery.join("type")).unwrap_or_default();
+    if kind.trim() != "Battery" {
+        return fail(format!("{} is not a battery", battery.display()));
+    }
+
+    if let Err(error) = thresholds.check() {
+        return fail(error);
+    }
+
+    match write_thresholds(&battery, thresholds) {
+        Ok(()) => 0,
+        Err(error) => fail(format!("unable to write the charge thresholds: {}", error)),
+    }
+}
@@ -0,0 +1,143 @@
+use log::{debug, info};
+use std::{fmt, time::Duration};
+
+use crate::{
+    battery::Adapter, charge_control::ChargeThresholds, clock::Clock, config::NotChargingConfig,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChargingFault {
+    // The adapter is connected yet the battery keeps draining.
+    CapacityDropping,
+    // The kernel reports "Not charging" below the charge limit, e.g. a weak
+    // adapter or a thermal pause.
+    NotCharging,
+    // Either of the above while the adapter advertises less power than the
+    // configured minimum, typically a phone charger on a USB-C port.
+    WeakAdapter(u32),
+}
+
+impl fmt::Display for ChargingFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChargingFault::CapacityDropping => write!(f, "the capacity is still dropping"),
+            ChargingFault::NotCharging => write!(f, "the battery is not charging"),
+            ChargingFault::WeakAdapter(watts) => {
+                write!(f, "the {}W adapter can't keep up", watts)
+            }
+        }
+    }
+}
+
+// Watches the samples taken while plugged in and reports a fault once it has
+// lasted longer than the configured grace period.
+pub struct ChargingMonitor {
+    capacity_drop: u8,
+    grace: Duration,
+    charge_limit: u8,
+    min_adapter_watts: u32,
+    peak_capacity: Option<u8>,
+    fault_since: Option<Duration>,
+    notified: bool,
+    clock: Clock,
+}
+
+impl ChargingMonitor {
+    pub fn new(config: &NotChargingConfig, clock: Clock) -> Self {
+        Self {
+            capacity_drop: config.capacity_drop,
+            grace: Duration::from_secs(config.grace_s),
+            charge_limit: config.charge_limit,
+            min_adapter_watts: config.min_adapter_watts.unwrap_or_default(),
+            peak_capacity: None,
+            fault_since: None,
+            notified: false,
+            clock,
+        }
+    }
+
+    // A fault being watched is kept across a reload.
+    pub fn set_config(&mut self, config: &NotChargingConfig) {
+        self.capacity_drop = config.capacity_drop;
+        self.grace = Duration::from_secs(config.grace_s);
+        self.charge_limit = config.charge_limit;
+        self.min_adapter_watts = config.min_adapter_watts.unwrap_or_default();
+    }
+
+    // Forgets the samples taken so far, they tell nothing about the charger
+    // once the system slept.
+    pub fn reset(&mut self) {
+        self.peak_capacity = None;
+        self.fault_since = None;
+        self.notified = false;
+    }
+
+    pub fn update(
+        &mut self,
+        plugged_in: bool,
+        status: &str,
+        capacity: u8,
+        thresholds: ChargeThresholds,
+        adapter: Option<&Adapter>,
+    ) -> Option<ChargingFault> {
+        if !plugged_in {
+            self.peak_capacity = None;
+            self.fault_since = None;
+            self.notified = false;
+            return None;
+        }
+
+        let peak = self.peak_capacity.map_or(capacity, |p| p.max(capacity));
+        self.peak_capacity = Some(peak);
+
+        // A battery held at its charge limit legitimately reports "Not charging",
+        // and so does one that drained from it without falling below the start
+        // threshold yet. The one point of tolerance covers controllers that stop
+        // just below it. The capacity lost meanwhile is no fault either.
+        let charge_limit = thresholds
+            .start
+            .or(thresholds.end)
+            .unwrap_or(self.charge_limit);
+
+        let held = status == "Not charging" && capacity >= charge_limit.saturating_sub(1);
+
+        let fault = if held {
+            None
+        } else if peak.saturating_sub(capacity) >= self.capacity_drop {
+            Some(ChargingFault::CapacityDropping)
+        } else if status == "Not charging" {
+            Some(ChargingFault::NotCharging)
+        } else {
+            None
+        };
+
+        let weak_adapter = adapter
+            .and_then(|a| a.max_watts)
+            .filter(|watts| *watts < self.min_adapter_watts as f64);
+
+        let fault = match (fault, weak_adapter) {
+            (Some(_), Some(watts)) => Some(ChargingFault::WeakAdapter(watts.round() as u32)),
+            (fault, _) => fault,
+        };
+
+        let Some(fault) = fault else {
+            if self.fault_since.take().is_some() {
+                info!("the battery is charging normally again");
+            }
+
+            self.notified = false;
+            return None;
+        };
+
+        let now = self.clock.now();
+        let elapsed = now - *self.fault_since.get_or_insert(now);
+        debug!("charging fault ({}) for {:?}", fault, elapsed);
+
+        if self.notified || elapsed < self.grace {
+            return None;
+        }
+
+        self.notified = true;
+        Some(fault)
+    }
+}
@@ -0,0 +1,382 @@
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use std::{fmt, fs, path::Path};
+use toml::Spanned;
+
+use crate::config::{get_position, Config, Format, ParseError, Profile, ProfileRule};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+pub struct Diagnostic {
+    pub severity: Severity,
+    // 1-based line and column, None when the problem isn't tied to a key or
+    // the format doesn't keep track of positions.
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+// The file as written, keeping where every key and value is when the format
+// allows it. Only integers are kept since they are the only values checked
+// here, serde already reports values of the wrong type, and arrays for the
+// tables they may hold.
+enum Node {
+    Table(Vec<Entry>),
+    Array(Vec<Node>),
+    Integer(i64),
+    Other,
+}
+
+struct Entry {
+    key: String,
+    key_position: Option<(usize, usize)>,
+    value: Node,
+    value_position: Option<(usize, usize)>,
+}
+
+// TOML is the only format whose deserializer reports spans. Only keys are
+// spanned, tables implied by a dotted header such as `[sinks.command]` have no
+// span, which Spanned values can't deal with.
+enum SpannedNode {
+    Table(Vec<(Spanned<String>, SpannedNode)>),
+    Array(Vec<SpannedNode>),
+    Integer(i64),
+    Other,
+}
+
+impl<'de> Deserialize<'de> for SpannedNode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(SpannedNodeVisitor)
+    }
+}
+
+struct SpannedNodeVisitor;
+
+impl<'de> Visitor<'de> for SpannedNodeVisitor {
+    type Value = SpannedNode;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "any TOML value")
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<SpannedNode, E> {
+        Ok(SpannedNode::Other)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<SpannedNode, E> {
+        Ok(SpannedNode::Integer(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<SpannedNode, E> {
+        Ok(SpannedNode::Integer(value.try_into().unwrap_or(i64::MAX)))
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<SpannedNode, E> {
+        Ok(SpannedNode::Other)
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<SpannedNode, E> {
+        Ok(SpannedNode::Other)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<SpannedNode, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item)
+        }
+
+        Ok(SpannedNode::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<SpannedNode, A::Error> {
+        let mut entries = Vec::new();
+        while let Some(entry) = map.next_entry()? {
+            entries.push(entry)
+        }
+
+        Ok(SpannedNode::Table(entries))
+    }
+}
+
+impl Node {
+    fn parse(content: &str, format: Format) -> Result<Node, ParseError> {
+        match format {
+            Format::Toml => Ok(Node::from_spanned(format.deserialize(content)?, content)),
+            Format::Yaml | Format::Json => Ok(Node::from_value(format.deserialize(content)?)),
+        }
+    }
+
+    fn from_spanned(node: SpannedNode, content: &str) -> Node {
+        match node {
+            SpannedNode::Table(entries) => Node::Table(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| Entry {
+                        key_position: Some(get_position(content, key.span().start)),
+                        value_position: find_value_offset(content, key.span().end)
+                            .map(|offset| get_position(content, offset)),
+                        key: key.into_inner(),
+                        value: Node::from_spanned(value, content),
+                    })
+                    .collect(),
+            ),
+            SpannedNode::Array(items) => Node::Array(
+                items
+                    .into_iter()
+                    .map(|item| Node::from_spanned(item, content))
+                    .collect(),
+            ),
+            SpannedNode::Integer(n) => Node::Integer(n),
+            SpannedNode::Other => Node::Other,
+        }
+    }
+
+    fn from_value(value: serde_json::Value) -> Node {
+        match value {
+            serde_json::Value::Object(map) => Node::Table(
+                map.into_iter()
+                    .map(|(key, value)| Entry {
+                        key,
+                        key_position: None,
+                        value: Node::from_value(value),
+                        value_position: None,
+                    })
+                    .collect(),
+            ),
+            serde_json::Value::Array(items) => {
+                Node::Array(items.into_iter().map(Node::from_value).collect())
+            }
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(n) => Node::Integer(n),
+                None => n.as_u64().map_or(Node::Other, |_| Node::Integer(i64::MAX)),
+            },
+            _ => Node::Other,
+        }
+    }
+
+    // Looks a dotted path such as `warn.threshold` up.
+    fn find(&self, path: &str) -> Option<&Entry> {
+        let Node::Table(entries) = self else {
+            return None;
+        };
+
+        let (first, rest) = match path.split_once('.') {
+            Some((first, rest)) => (first, Some(rest)),
+            None => (path, None),
+        };
+
+        let entry = entries.iter().find(|e| e.key == first)?;
+        match rest {
+            Some(rest) => entry.value.find(rest),
+            None => Some(entry),
+        }
+    }
+}
+
+// Where the value of the key ending at `offset` starts, the keys of table
+// headers have none.
+fn find_value_offset(content: &str, offset: usize) -> Option<usize> {
+    let value = content
+        .get(offset..)?
+        .trim_start_matches([' ', '\t'])
+        .strip_prefix('=')?
+        .trim_start_matches([' ', '\t']);
+
+    Some(content.len() - value.len())
+}
+
+// Integer settings whose out of range values would silently be replaced by
+// their defaults.
+const RANGES: [(&str, i64, i64); 6] = [
+    ("reminder.threshold", 1, 100),
+    ("warn.threshold", 1, 100),
+    ("threat.threshold", 1, 100),
+    ("peripherals.warn.threshold", 1, 100),
+    ("peripherals.threat.threshold", 1, 100),
+    ("not_charging.charge_limit", 1, 100),
+];
+
+// `fs_checks` looks for the files the config points to, which builds in a
+// sandbox can't see.
+pub fn check_config(content: &str, format: Format, fs_checks: bool) -> Vec<Diagnostic> {
+    let error = |position: Option<(usize, usize)>, message: String| Diagnostic {
+        severity: Severity::Error,
+        position,
+        message,
+    };
+
+    let (config, document) = match Config::parse_str(content, format)
+        .and_then(|config| Ok((config, Node::parse(content, format)?)))
+    {
+        Ok(parsed) => parsed,
+        Err(e) => return vec![error(e.position, e.message)],
+    };
+
+    let mut diagnostics = Vec::new();
+
+    // Every key the program knows about is in the serialized defaults.
+    let known = serde_json::to_value(Config::default()).expect("serialize default config");
+    find_unknown_keys(&document, &known, "", &mut diagnostics);
+
+    for (path, min, max) in RANGES {
+        if let Some(entry) = document.find(path) {
+            if let Node::Integer(n) = entry.value {
+                if n < min || n > max {
+                    diagnostics.push(error(
+                        entry.value_position,
+                        format!("'{path}' must be between {min} and {max}, got {n}"),
+                    ))
+                }
+            }
+        }
+    }
+
+    let get_value_position = |path: &str| document.find(path).and_then(|e| e.value_position);
+
+    for problem in config.check() {
+        diagnostics.push(error(get_value_position(&problem.key), problem.message))
+    }
+
+    let icon_path = config
+        .icon_path
+        .as_ref()
+        .filter(|p| fs_checks && !p.is_empty());
+    if let Some(icon_path) = icon_path {
+        if !Path::new(icon_path).exists() {
+            diagnostics.push(error(
+                get_value_position("icon_path"),
+                format!("icon '{icon_path}' does not exist"),
+            ))
+        }
+    }
+
+    diagnostics.sort_by_key(|d| d.position.unwrap_or((usize::MAX, 0)));
+    diagnostics
+}
+
+// Items of the arrays of tables, whose defaults are empty, with every optional
+// key set so it's known.
+fn get_known_item(path: &str) -> Option<serde_json::Value> {
+    let item = match path {
+        "profile_rules" => ProfileRule {
+            profile: String::new(),
+            plugged_for_s: Some(0),
+            unplugged_for_s: Some(0),
+            between: Some(String::new()),
+            process: Some(String::new()),
+        },
+        _ => return None,
+    };
+
+    Some(serde_json::to_value(item).expect("serialize array item"))
+}
+
+fn find_unknown_keys(
+    node: &Node,
+    known: &serde_json::Value,
+    prefix: &str,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let Node::Table(entries) = node else {
+        return;
+    };
+
+    for entry in entries {
+        let path = format!("{}{}", prefix, entry.key);
+
+        match known.get(&entry.key) {
+            // Profiles are named by the user, only their own keys are known.
+            Some(_) if path == "profiles" => {
+                let known = serde_json::to_value(Profile::default()).expect("serialize profile");
+
+                if let Node::Table(profiles) = &entry.value {
+                    for profile in profiles {
+                        let prefix = format!("profiles.{}.", profile.key);
+                        find_unknown_keys(&profile.value, &known, &prefix, diagnostics)
+                    }
+                }
+            }
+            Some(known) if known.is_object() => {
+                find_unknown_keys(&entry.value, known, &format!("{path}."), diagnostics)
+            }
+            Some(known) if known.is_array() => {
+                let Node::Array(items) = &entry.value else {
+                    continue;
+                };
+                let Some(known) = get_known_item(&path).or_else(|| known.get(0).cloned()) else {
+                    continue;
+                };
+
+                for (index, item) in items.iter().enumerate() {
+                    let prefix = format!("{path}[{index}].");
+                    find_unknown_keys(item, &known, &prefix, diagnostics)
+                }
+            }
+            Some(_) => {}
+            None => diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                position: entry.key_position,
+                message: format!("unknown key '{path}' is ignored"),
+            }),
+        }
+    }
+}
+
+// Prints the diagnostics of the config file at `path` and returns the exit
+// code, warnings only fail the check when `strict` is set.
+pub fn run_check_config(path: &str, strict: bool, fs_checks: bool) -> i32 {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(error) => {
+            println!("{path}: error: unable to read the config: {error}");
+            return 1;
+        }
+    };
+
+    let diagnostics = check_config(&content, Format::from_path(path), fs_checks);
+
+    for diagnostic in &diagnostics {
+        match diagnostic.position {
+            Some((line, column)) => {
+                println!(
+                    "{path}:{line}:{column}: {}: {}",
+                    diagnostic.severity,
+                    diagnostic.message.replace('\n', ", ")
+                )
+            }
+            None => println!("{path}: {}: {}", diagnostic.severity, diagnostic.message),
+        }
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+
+    if diagnostics.is_empty() {
+        println!("{path}: the config is valid");
+    } else {
+        println!("{path}: {errors} error(s), {warnings} warning(s)");
+    }
+
+    if errors > 0 || (strict && warnings > 0) {
+        1
+    } else {
+        0
+    }
+}
@@ -1,16 +1,192 @@
 use anstyle::{AnsiColor, Color, Style};
-use clap::{builder::Styles, Parser};
+use clap::{builder::Styles, ArgGroup, Parser, Subcommand};
+use serde_json::Value;
+
+use crate::{
+    clock::Speed,
+    layers::{check_override, parse_value, Source},
+    logind::Bus,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 #[command(styles=get_styles())]
+#[command(group(ArgGroup::new("simulated").args(["debug_file", "replay"])))]
 pub struct Args {
     #[arg(short, long)]
     /// To simulate battery states (yaml).
     pub debug_file: Option<String>,
-    /// The config file path (toml).
+    /// The config file path (toml, yaml or json).
     #[arg(short, long)]
     pub config_file: Option<String>,
+    /// Directory holding the power supply class [default: /sys/class/power_supply].
+    #[arg(long, env = "BATTERY_NOTIFIER_POWER_SUPPLY_ROOT")]
+    pub power_supply_root: Option<String>,
+    /// Directory holding the backlight class [default: /sys/class/backlight].
+    #[arg(long, env = "BATTERY_NOTIFIER_BACKLIGHT_ROOT")]
+    pub backlight_root: Option<String>,
+    /// Play back a capture made with --record instead of reading the battery.
+    #[arg(long, conflicts_with = "debug_file")]
+    pub replay: Option<String>,
+    /// Write every battery reading to this file (JSON lines).
+    #[arg(long)]
+    pub record: Option<String>,
+    /// How fast time goes by, a factor such as `10x` or `virtual` to skip the waits.
+    // A real battery doesn't change any faster, the virtual clock would only
+    // spin the loop.
+    #[arg(long, default_value = "1x", requires_if("virtual", "simulated"))]
+    pub speed: Speed,
+    /// Print the notifications, sounds and hooks as JSON lines instead of firing them.
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Run as the system service, events go to the agents of the user sessions.
+    #[arg(long)]
+    pub system: bool,
+    /// Bus to reach logind on, `session` to test against a mock.
+    #[arg(long, value_enum, default_value = "system")]
+    pub logind_bus: Bus,
+    /// Bus to reach power-profiles-daemon on, `session` to test against a mock.
+    #[arg(long, value_enum, default_value = "system")]
+    pub power_profiles_bus: Bus,
+    /// Overrides `reminder.threshold`.
+    #[arg(long)]
+    pub reminder_threshold: Option<u8>,
+    /// Overrides `warn.threshold`.
+    #[arg(long)]
+    pub warn_threshold: Option<u8>,
+    /// Overrides `threat.threshold`.
+    #[arg(long)]
+    pub threat_threshold: Option<u8>,
+    /// Overrides `interval_ms`.
+    #[arg(long)]
+    pub interval_ms: Option<u64>,
+    /// Overrides any config key, e.g. `--set hooks.on_plug='notify-send plugged'`.
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Report every problem of the config file and exit non-zero on errors.
+    CheckConfig {
+        /// Fail on warnings too, such as unknown keys.
+        #[arg(long)]
+        strict: bool,
+        /// Skip checking that the files the config points to exist, for builds in a sandbox.
+        #[arg(long)]
+        no_fs_checks: bool,
+    },
+    /// Write the default config, with every key documented, to the config file path.
+    InitConfig {
+        /// Replace the config file if it already exists.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the JSON Schema of the config file.
+    Schema,
+    /// Inspect the config assembled from every layer.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Control the running daemon.
+    #[command(subcommand)]
+    Ctl(CtlCommand),
+    /// Render the notifications of the system service in this session.
+    Agent {
+        /// Socket the system service sends its events on [default: /run/battery-notifier/events.sock].
+        #[arg(long)]
+        events_socket: Option<String>,
+    },
+    /// Write the charge thresholds of a battery, run as root by the daemon's helper.
+    #[command(hide = true)]
+    ChargeControlHelper {
+        /// Name of the battery under /sys/class/power_supply.
+        battery: String,
+        #[arg(long)]
+        start: Option<u8>,
+        #[arg(long)]
+        end: Option<u8>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the merged config along with where each value comes from.
+    Dump {
+        /// Include the defaults, i.e. the config actually used.
+        #[arg(long)]
+        effective: bool,
+    },
+}
+
+impl Args {
+    // The config values set from the command line, the dedicated flags first
+    // so `--set` can still replace them.
+    pub fn get_overrides(&self) -> Result<Vec<(String, Value, Source)>, String> {
+        let flags = [
+            (
+                "reminder.threshold",
+                "--reminder-threshold",
+                self.reminder_threshold.map(Value::from),
+            ),
+            (
+                "warn.threshold",
+                "--warn-threshold",
+                self.warn_threshold.map(Value::from),
+            ),
+            (
+                "threat.threshold",
+                "--threat-threshold",
+                self.threat_threshold.map(Value::from),
+            ),
+            (
+                "interval_ms",
+                "--interval-ms",
+                self.interval_ms.map(Value::from),
+            ),
+        ];
+
+        let mut overrides: Vec<(String, Value, Source)> = flags
+            .into_iter()
+            .filter_map(|(path, flag, value)| {
+                value.map(|value| (path.to_string(), value, Source::Cli(flag.to_string())))
+            })
+            .collect();
+
+        for raw in &self.overrides {
+            let (path, value) = raw
+                .split_once('=')
+                .ok_or_else(|| format!("--set expects KEY=VALUE, got '{}'", raw))?;
+
+            let path = path.trim();
+            let value = check_override(path, parse_value(value.trim()))?;
+            overrides.push((
+                path.to_string(),
+                value,
+                Source::Cli(format!("--set {}", path)),
+            ))
+        }
+
+        Ok(overrides)
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CtlCommand {
+    /// Select a profile by hand, or print the active one.
+    Profile {
+        /// Profile to select, `auto` to let the rules select it again.
+        name: Option<String>,
+    },
+    /// Set the capacity charging stops at, or print the charge thresholds.
+    ChargeLimit {
+        /// Capacity charging stops at.
+        end: Option<u8>,
+        /// Capacity charging starts again below.
+        #[arg(long, requires = "end")]
+        start: Option<u8>,
+    },
 }
 
 fn get_styles() -> Styles {
@@ -0,0 +1,102 @@
+use std::{
+    cell::Cell,
+    fmt,
+    rc::Rc,
+    str::FromStr,
+    sync::mpsc::Receiver,
+    thread,
+    time::{Duration, Instant},
+};
+
+// How fast the notify loop and the debug simulator see time passing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    // Real time multiplied by the given factor, e.g. `10x`.
+    Factor(f64),
+    // Waits return immediately and only move the clock forward, so a scenario
+    // plays as fast as possible and always samples the same points.
+    Virtual,
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed::Factor(1.0)
+    }
+}
+
+impl FromStr for Speed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "virtual" {
+            return Ok(Speed::Virtual);
+        }
+
+        let factor = s
+            .strip_suffix('x')
+            .unwrap_or(s)
+            .parse::<f64>()
+            .map_err(|_| format!("expected a factor such as `10x` or `virtual`, got {s:?}"))?;
+
+        if !factor.is_finite() || factor <= 0.0 {
+            return Err("the speed factor must be greater than zero".to_string());
+        }
+
+        Ok(Speed::Factor(factor))
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Speed::Factor(factor) => write!(f, "{}x", factor),
+            Speed::Virtual => write!(f, "virtual"),
+        }
+    }
+}
+
+// Monotonic time elapsed since the program started. Clones share the same
+// timeline, so the simulator and the loop always agree on the current time.
+#[derive(Clone)]
+pub enum Clock {
+    Real { started_at: Instant, factor: f64 },
+    Virtual(Rc<Cell<Duration>>),
+}
+
+impl Clock {
+    pub fn new(speed: Speed) -> Self {
+        match speed {
+            Speed::Factor(factor) => Clock::Real {
+                started_at: Instant::now(),
+                factor,
+            },
+            Speed::Virtual => Clock::Virtual(Rc::new(Cell::new(Duration::ZERO))),
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        match self {
+            Clock::Real { started_at, factor } => started_at.elapsed().mul_f64(*factor),
+            Clock::Virtual(now) => now.get(),
+        }
+    }
+
+    pub fn sleep(&self, duration: Duration) {
+        match self {
+            Clock::Real { factor, .. } => thread::sleep(duration.div_f64(*factor)),
+            Clock::Virtual(now) => now.set(now.get() + duration),
+        }
+    }
+
+    // Sleeps like `sleep` but returns early with the first message `receiver`
+    // gets. The virtual clock doesn't wait, it only sees messages already sent.
+    pub fn sleep_or_recv<T>(&self, duration: Duration, receiver: &Receiver<T>) -> Option<T> {
+        match self {
+            Clock::Real { factor, .. } => receiver.recv_timeout(duration.div_f64(*factor)).ok(),
+            Clock::Virtual(_) => {
+                self.sleep(duration);
+                receiver.try_recv().ok()
+            }
+        }
+    }
+}
@@ -1,51 +1,661 @@
-use log::{error, info, warn};
-use serde::Deserialize;
-use std::{env, fs, path::Path, process};
+use log::{error, warn};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    process,
+};
 
-#[derive(Debug, Clone, Deserialize)]
+use crate::{
+    battery::BatteryNotificationLevel,
+    charge_control::ChargeThresholds,
+    event::{Event, EventKind},
+    power_profiles::POWER_PROFILES,
+    profile::TimeWindow,
+    xdg::{get_app_dir, BaseDir, XdgError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    Desktop,
+    Terminal,
+    Log,
+    Webhook,
+    Command,
+}
+
+impl SinkKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SinkKind::Desktop => "desktop",
+            SinkKind::Terminal => "terminal",
+            SinkKind::Log => "log",
+            SinkKind::Webhook => "webhook",
+            SinkKind::Command => "command",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
 pub struct Bound {
+    /// Capacity percentage at or below which the notification is sent.
     #[serde(default)]
+    #[schemars(range(max = 100))]
     pub threshold: u8,
+    /// Notification title, supports `${{capacity}}` and `${{time_remaining}}`.
     #[serde(default)]
     pub title: String,
+    /// Notification body, supports the same placeholders as the title.
     #[serde(default)]
     pub content: String,
+    /// Overrides `sinks.enabled` for this level only.
+    #[serde(default)]
+    pub sinks: Vec<SinkKind>,
+}
+
+impl Bound {
+    pub fn render_title(&self, vars: &[(&str, String)]) -> String {
+        render_template(&self.title, vars)
+    }
+
+    pub fn render_content(&self, vars: &[(&str, String)]) -> String {
+        render_template(&self.content, vars)
+    }
+
+    // The bound with the values it leaves unset taken from `other`.
+    fn or(&self, other: &Bound) -> Bound {
+        let or_else = |value: &String, other: &String| {
+            if value.is_empty() {
+                other.to_owned()
+            } else {
+                value.to_owned()
+            }
+        };
+
+        Bound {
+            threshold: match self.threshold {
+                1..=100 => self.threshold,
+                _ => other.threshold,
+            },
+            title: or_else(&self.title, &other.title),
+            content: or_else(&self.content, &other.content),
+            sinks: if self.sinks.is_empty() {
+                other.sinks.to_owned()
+            } else {
+                self.sinks.to_owned()
+            },
+        }
+    }
 }
 
-impl Default for Bound {
+/// Settings replacing the base ones while the profile is active, any key left
+/// out keeps its base value.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct Profile {
+    /// Replaces `reminder`, key by key.
+    #[serde(default)]
+    pub reminder: Bound,
+    /// Replaces `warn`, key by key.
+    #[serde(default)]
+    pub warn: Bound,
+    /// Replaces `threat`, key by key.
+    #[serde(default)]
+    pub threat: Bound,
+    /// Replaces `sound`.
+    #[serde(default)]
+    pub sound: Option<bool>,
+    /// Replaces `repeat_s`.
+    #[serde(default)]
+    pub repeat_s: Option<u64>,
+}
+
+/// Selects a profile when every condition it sets holds.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct ProfileRule {
+    /// Name of the profile in `profiles`.
+    pub profile: String,
+    /// The adapter has been plugged in for at least this many seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plugged_for_s: Option<u64>,
+    /// The laptop has been running on battery for at least this many seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unplugged_for_s: Option<u64>,
+    /// Local time window such as `22:00-07:00`, which may wrap around midnight.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub between: Option<String>,
+    /// A process with this name is running, as shown in `/proc/<pid>/comm`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process: Option<String>,
+}
+
+/// Notification for an event that isn't tied to a capacity threshold.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct Alert {
+    /// Send the notification at all, hooks run either way.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Notification title, supports `${{capacity}}` and `${{time_remaining}}`.
+    #[serde(default)]
+    pub title: String,
+    /// Notification body, supports the same placeholders as the title.
+    #[serde(default)]
+    pub content: String,
+    /// Overrides `sinks.enabled` for this event only.
+    #[serde(default)]
+    pub sinks: Vec<SinkKind>,
+}
+
+impl Default for Alert {
     fn default() -> Self {
         Self {
-            threshold: 0,
+            enabled: true,
             title: String::new(),
             content: String::new(),
+            sinks: Vec::new(),
         }
     }
 }
 
-impl Bound {
-    pub fn render_title(&self, capacity: u8) -> String {
-        self.title
-            .replace("${{capacity}}", capacity.to_string().as_str())
+impl Alert {
+    pub fn render_title(&self, vars: &[(&str, String)]) -> String {
+        render_template(&self.title, vars)
+    }
+
+    pub fn render_content(&self, vars: &[(&str, String)]) -> String {
+        render_template(&self.content, vars)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct NotChargingConfig {
+    /// Send the notification at all, hooks run either way.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Notification title, also supports `${{reason}}`, `${{adapter}}` and `${{adapter_watts}}`.
+    #[serde(default)]
+    pub title: String,
+    /// Notification body, supports the same placeholders as the title.
+    #[serde(default)]
+    pub content: String,
+    /// Overrides `sinks.enabled` for this event only.
+    #[serde(default)]
+    pub sinks: Vec<SinkKind>,
+    /// Capacity points lost while plugged in before the charger is considered unable to keep up.
+    #[serde(default)]
+    pub capacity_drop: u8,
+    /// How long a fault must last before notifying, so short thermal pauses are ignored.
+    #[serde(default)]
+    pub grace_s: u64,
+    /// Used when the battery doesn't expose `charge_control_end_threshold`.
+    #[serde(default)]
+    #[schemars(range(max = 100))]
+    pub charge_limit: u8,
+    /// Adapters advertising less than this are blamed when charging fails, 0 never blames them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_adapter_watts: Option<u32>,
+}
+
+impl Default for NotChargingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            title: String::new(),
+            content: String::new(),
+            sinks: Vec::new(),
+            capacity_drop: 0,
+            grace_s: 0,
+            charge_limit: 0,
+            min_adapter_watts: None,
+        }
+    }
+}
+
+impl NotChargingConfig {
+    pub fn render_title(&self, vars: &[(&str, String)]) -> String {
+        render_template(&self.title, vars)
     }
 
-    pub fn render_content(&self, capacity: u8) -> String {
-        self.content
-            .replace("${{capacity}}", capacity.to_string().as_str())
+    pub fn render_content(&self, vars: &[(&str, String)]) -> String {
+        render_template(&self.content, vars)
+    }
+}
+
+/// Syntax of a config file, told by its extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Format {
+    /// Files without a known extension are read as TOML, as they always were.
+    pub fn from_path(path: &str) -> Format {
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => Format::Yaml,
+            Some("json") => Format::Json,
+            _ => Format::Toml,
+        }
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(&self, content: &str) -> Result<T, ParseError> {
+        match self {
+            Format::Toml => toml::from_str(content).map_err(|e| ParseError {
+                message: e.message().trim_end().replace('\n', ", "),
+                position: e.span().map(|span| get_position(content, span.start)),
+            }),
+            Format::Yaml => serde_yaml::from_str(content).map_err(|e| {
+                ParseError::located(e.to_string(), e.location().map(|l| (l.line(), l.column())))
+            }),
+            Format::Json => serde_json::from_str(content).map_err(|e| {
+                let position = (e.line() > 0).then(|| (e.line(), e.column()));
+                ParseError::located(e.to_string(), position)
+            }),
+        }
+    }
+}
+
+/// A config that couldn't be deserialized, with the 1-based line and column at fault.
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub position: Option<(usize, usize)>,
+}
+
+impl ParseError {
+    // serde_yaml and serde_json append the position to their messages, it is
+    // kept apart instead so every format reports it the same way.
+    fn located(message: String, position: Option<(usize, usize)>) -> Self {
+        let message = match position {
+            Some((line, column)) => message
+                .trim_end_matches(&format!(" at line {} column {}", line, column))
+                .to_string(),
+            None => message,
+        };
+
+        Self { message, position }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => {
+                write!(f, "{} at line {} column {}", self.message, line, column)
+            }
+            None => write!(f, "{}", self.message),
+        }
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl Error for ParseError {}
+
+/// Turns a byte offset into a 1-based line and column.
+pub fn get_position(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+
+    (line, column)
+}
+
+/// Something wrong with a config, along with the dotted path of the key at fault.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub key: String,
+    pub message: String,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_power_saver() -> String {
+    "power-saver".to_string()
+}
+
+fn default_warn_dim() -> u8 {
+    30
+}
+
+fn default_threat_dim() -> u8 {
+    50
+}
+
+// Replaces every `${{name}}` placeholder with its value.
+pub fn render_template(template: &str, vars: &[(&str, String)]) -> String {
+    vars.iter()
+        .fold(template.to_owned(), |rendered, (name, value)| {
+            rendered.replace(&format!("${{{{{}}}}}", name), value)
+        })
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct Config {
+    /// Icon shown in desktop notifications, the bundled one when unset.
     #[serde(default)]
     pub icon_path: Option<String>,
+    /// First and mildest low battery notification.
     #[serde(default)]
     pub reminder: Bound,
+    /// Last notification before the battery runs out.
     #[serde(default)]
     pub threat: Bound,
+    /// Notification between the reminder and the threat.
     #[serde(default)]
     pub warn: Bound,
+    /// Notification sent when the charger is unplugged.
+    #[serde(default)]
+    pub unplug: Alert,
+    /// Notification sent when the charger is connected but the battery doesn't charge.
+    #[serde(default)]
+    pub not_charging: NotChargingConfig,
+    /// Low battery notifications for wireless devices.
+    #[serde(default)]
+    pub peripherals: PeripheralsConfig,
+    /// Time between two battery checks.
     #[serde(default)]
     pub interval_ms: u64,
+    /// Adapts the time between two battery checks to the battery state.
+    #[serde(default)]
+    pub polling: PollingConfig,
+    /// Suspending at the threat level.
+    #[serde(default)]
+    pub sleep: SleepConfig,
+    /// Switches power-profiles-daemon profiles at low levels.
+    #[serde(default)]
+    pub power_profiles: PowerProfilesConfig,
+    /// Dims the screen at low levels.
+    #[serde(default)]
+    pub backlight: BacklightConfig,
+    /// Charge thresholds of the battery.
+    #[serde(default)]
+    pub charge_control: ChargeControlConfig,
+    /// Own `io.github.luisnquin.BatteryNotifier` on the session bus for desktop widgets.
+    #[serde(default = "default_true")]
+    pub dbus_service: bool,
+    /// Play a sound along with notifications.
+    #[serde(default = "default_true")]
+    pub sound: bool,
+    /// Where notifications are sent.
+    #[serde(default)]
+    pub sinks: SinksConfig,
+    /// Shell commands run on battery events.
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// Notify the current low battery level again after this many seconds, 0 to notify it once.
+    #[serde(default)]
+    pub repeat_s: u64,
+    /// Named sets of settings, selected by `profile_rules` or `battery-notifier ctl profile`.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Rules selecting a profile, the first one that matches wins.
+    #[serde(default)]
+    pub profile_rules: Vec<ProfileRule>,
+}
+
+/// Batteries of wireless mice, keyboards, headsets and alike.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct PeripheralsConfig {
+    /// Watch peripheral batteries as well.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Notification for a low peripheral battery, `${{model_name}}` names the device.
+    #[serde(default)]
+    pub warn: Bound,
+    /// Notification for a nearly empty peripheral battery.
+    #[serde(default)]
+    pub threat: Bound,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct SinksConfig {
+    /// Sinks used by every level that doesn't declare its own.
+    #[serde(default)]
+    pub enabled: Vec<SinkKind>,
+    /// Options of the terminal sink.
+    #[serde(default)]
+    pub terminal: TerminalSinkConfig,
+    /// Options of the webhook sink.
+    #[serde(default)]
+    pub webhook: WebhookSinkConfig,
+    /// Options of the command sink.
+    #[serde(default)]
+    pub command: CommandSinkConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct TerminalSinkConfig {
+    /// Also broadcast the message to every logged in TTY through `wall`.
+    #[serde(default)]
+    pub wall: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct WebhookSinkConfig {
+    /// Endpoint receiving every event as a JSON POST request.
+    #[serde(default)]
+    pub url: String,
+    /// Requests taking longer than this are abandoned.
+    #[serde(default)]
+    pub timeout_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct CommandSinkConfig {
+    /// Shell command line, run through `sh -c` with the event in its environment.
+    #[serde(default)]
+    pub run: String,
+    /// Commands still running after this are killed.
+    #[serde(default)]
+    pub timeout_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CriticalAction {
+    /// Show `sleep.warning` and let the system suspend.
+    #[default]
+    Warn,
+    /// Hibernate when the lid closes at the threat level, other suspends are warned about.
+    Hibernate,
+}
+
+/// What happens when the system is about to suspend at the threat level.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct SleepConfig {
+    /// Hold suspends back with a logind delay lock to act at the threat level first.
+    #[serde(default)]
+    pub inhibit: bool,
+    /// `warn` or `hibernate`.
+    #[serde(default)]
+    pub critical_action: CriticalAction,
+    /// How long the warning holds the suspend back, logind's `InhibitDelayMaxSec` caps it.
+    #[serde(default)]
+    pub warn_ms: u64,
+    /// Last notification before suspending at the threat level.
+    #[serde(default)]
+    pub warning: Alert,
+}
+
+/// Profiles set through power-profiles-daemon as the battery drains, an empty one keeps the active profile.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct PowerProfilesConfig {
+    /// Switch profiles at all.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Set when entering the reminder level.
+    #[serde(default)]
+    pub reminder: String,
+    /// Set when entering the warn level.
+    #[serde(default = "default_power_saver")]
+    pub warn: String,
+    /// Set when entering the threat level.
+    #[serde(default = "default_power_saver")]
+    pub threat: String,
+    /// Set the profile active before the first switch again once the adapter is plugged in.
+    #[serde(default = "default_true")]
+    pub restore_on_plug: bool,
+}
+
+impl Default for PowerProfilesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            reminder: String::new(),
+            warn: default_power_saver(),
+            threat: default_power_saver(),
+            restore_on_plug: true,
+        }
+    }
+}
+
+impl PowerProfilesConfig {
+    /// The profile set when entering `level`, empty if there is none.
+    pub fn get_profile(&self, level: BatteryNotificationLevel) -> &str {
+        match level {
+            BatteryNotificationLevel::Reminder => &self.reminder,
+            BatteryNotificationLevel::Warn => &self.warn,
+            BatteryNotificationLevel::Threat => &self.threat,
+            _ => "",
+        }
+    }
+}
+
+/// Dims the screen as the battery drains, a percentage of the brightness before dimming.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct BacklightConfig {
+    /// Dim the screen at all.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Brightness taken away when entering the warn level, 0 to leave it.
+    #[serde(default = "default_warn_dim")]
+    #[schemars(range(max = 100))]
+    pub warn_percent: u8,
+    /// Brightness taken away when entering the threat level, 0 to leave it.
+    #[serde(default = "default_threat_dim")]
+    #[schemars(range(max = 100))]
+    pub threat_percent: u8,
+    /// Set the brightness from before dimming again once the adapter is plugged in.
+    #[serde(default = "default_true")]
+    pub restore_on_plug: bool,
+}
+
+impl Default for BacklightConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            warn_percent: default_warn_dim(),
+            threat_percent: default_threat_dim(),
+            restore_on_plug: true,
+        }
+    }
+}
+
+impl BacklightConfig {
+    /// The brightness percentage taken away when entering `level`.
+    pub fn get_dim_percent(&self, level: BatteryNotificationLevel) -> u8 {
+        match level {
+            BatteryNotificationLevel::Warn => self.warn_percent,
+            BatteryNotificationLevel::Threat => self.threat_percent,
+            _ => 0,
+        }
+    }
+}
+
+/// Charge thresholds written to the battery when the daemon starts, 0 leaves a threshold as it is.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ChargeControlConfig {
+    /// Charging starts again once the capacity falls below this.
+    #[serde(default)]
+    #[schemars(range(max = 100))]
+    pub start_threshold: u8,
+    /// Charging stops at this capacity.
+    #[serde(default)]
+    #[schemars(range(max = 100))]
+    pub end_threshold: u8,
+    /// Command prefix writing the thresholds as root when the daemon can't, such as `pkexec` or `sudo -n`.
+    #[serde(default)]
+    pub helper: String,
+}
+
+impl Default for ChargeControlConfig {
+    fn default() -> Self {
+        Self {
+            start_threshold: 0,
+            end_threshold: 0,
+            helper: "pkexec".to_string(),
+        }
+    }
+}
+
+impl ChargeControlConfig {
+    /// The thresholds to write, those left at 0 excluded.
+    pub fn get_thresholds(&self) -> ChargeThresholds {
+        ChargeThresholds {
+            start: (self.start_threshold > 0).then_some(self.start_threshold),
+            end: (self.end_threshold > 0).then_some(self.end_threshold),
+        }
+    }
+}
+
+/// How often the battery is checked when `adaptive` is on, `interval_ms` applies otherwise.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct PollingConfig {
+    /// Check less often while no threshold is near and the adapter is plugged in.
+    #[serde(default)]
+    pub adaptive: bool,
+    /// Shortest time between two checks, used close to a threshold.
+    #[serde(default)]
+    pub min_interval_ms: u64,
+    /// Longest time between two checks, which also bounds how late a plug or unplug is noticed.
+    #[serde(default)]
+    pub max_interval_ms: u64,
+}
+
+/// Shell commands run when the battery enters a level or the charger state changes.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+pub struct HooksConfig {
+    /// Run when the battery enters the reminder level.
+    #[serde(default)]
+    pub on_reminder: String,
+    /// Run when the battery enters the warn level.
+    #[serde(default)]
+    pub on_warn: String,
+    /// Run when the battery enters the threat level.
+    #[serde(default)]
+    pub on_threat: String,
+    /// Run when the charger is plugged in.
+    #[serde(default)]
+    pub on_plug: String,
+    /// Run when the charger is unplugged.
+    #[serde(default)]
+    pub on_unplug: String,
+    /// Run when the battery is fully charged.
+    #[serde(default)]
+    pub on_full: String,
+    /// Run when the charger is connected but the battery doesn't charge.
+    #[serde(default)]
+    pub on_not_charging: String,
+    /// Run when a peripheral battery enters a level.
+    #[serde(default)]
+    pub on_peripheral: String,
+    /// Run when the system is about to suspend at the threat level, with `sleep.inhibit` on.
+    #[serde(default)]
+    pub on_suspend: String,
+    /// Hooks still running after this are killed.
+    #[serde(default)]
+    pub timeout_ms: u64,
 }
 
 impl Default for Config {
@@ -54,16 +664,42 @@ impl Default for Config {
 
         Config {
             interval_ms: 700,
+            polling: PollingConfig {
+                adaptive: false,
+                min_interval_ms: 700,
+                max_interval_ms: 30000,
+            },
+            power_profiles: PowerProfilesConfig::default(),
+            backlight: BacklightConfig::default(),
+            charge_control: ChargeControlConfig::default(),
+            sleep: SleepConfig {
+                inhibit: false,
+                critical_action: CriticalAction::Warn,
+                warn_ms: 3000,
+                warning: Alert {
+                    enabled: true,
+                    title: "Suspending with a critical battery".to_string(),
+                    content: format!(
+                        "{}.\nThe battery may run out while the computer sleeps",
+                        default_body
+                    ),
+                    sinks: Vec::new(),
+                },
+            },
+            dbus_service: true,
+            sound: true,
             icon_path: None,
             reminder: Bound {
                 title: "Battery somewhat low".to_string(),
                 content: default_body.to_string(),
                 threshold: 30,
+                sinks: Vec::new(),
             },
             warn: Bound {
                 title: "Battery low".to_string(),
                 content: format!("{}.\nPlease connect your laptop", default_body),
                 threshold: 15,
+                sinks: Vec::new(),
             },
             threat: Bound {
                 title: "Battery very low".to_string(),
@@ -72,43 +708,352 @@ impl Default for Config {
                     default_body
                 ),
                 threshold: 5,
+                sinks: Vec::new(),
+            },
+            unplug: Alert {
+                enabled: true,
+                title: "Running on battery".to_string(),
+                content: format!(
+                    "{}.\nEstimated runtime: ${{{{time_remaining}}}}",
+                    default_body
+                ),
+                sinks: Vec::new(),
             },
+            not_charging: NotChargingConfig {
+                enabled: true,
+                title: "Battery not charging".to_string(),
+                content: format!(
+                    "{}.\nThe charger is connected but ${{{{reason}}}}.",
+                    default_body
+                ),
+                sinks: Vec::new(),
+                capacity_drop: 2,
+                grace_s: 60,
+                charge_limit: 100,
+                min_adapter_watts: Some(45),
+            },
+            peripherals: PeripheralsConfig {
+                enabled: false,
+                warn: Bound {
+                    title: "${{model_name}} battery low".to_string(),
+                    content: default_body.to_string(),
+                    threshold: 15,
+                    sinks: Vec::new(),
+                },
+                threat: Bound {
+                    title: "${{model_name}} battery very low".to_string(),
+                    content: format!("{}.\nCharge it before it turns off", default_body),
+                    threshold: 5,
+                    sinks: Vec::new(),
+                },
+            },
+            sinks: SinksConfig {
+                enabled: vec![SinkKind::Desktop],
+                terminal: TerminalSinkConfig { wall: false },
+                webhook: WebhookSinkConfig {
+                    url: String::new(),
+                    timeout_ms: 5000,
+                },
+                command: CommandSinkConfig {
+                    run: String::new(),
+                    timeout_ms: 30000,
+                },
+            },
+            hooks: HooksConfig {
+                timeout_ms: 30000,
+                ..Default::default()
+            },
+            repeat_s: 0,
+            profiles: BTreeMap::new(),
+            profile_rules: Vec::new(),
         }
     }
 }
 
 impl Config {
-    pub fn parse(config_path: String) -> Result<Self, Box<dyn std::error::Error>> {
-        let content = fs::read_to_string(config_path)?;
-        let config: Config = toml::from_str(&content)?;
+    pub fn parse_str(content: &str, format: Format) -> Result<Self, ParseError> {
+        let config: Config = format.deserialize(content)?;
 
         Ok(config.merge(Config::default()))
     }
 
-    pub fn parse_or_default(config_path: String) -> Self {
-        match Config::parse(config_path) {
-            Ok(config) => config,
-            Err(error) => {
-                error!("unable to parse user config: {}", error);
-                info!("default config will be used");
-                Config::default() // Provide a default value or handle it as needed
-            }
+    pub fn validate(&self) {
+        let problems = self.check();
+
+        for problem in &problems {
+            error!("{}", problem.message);
+        }
+
+        if !problems.is_empty() {
+            process::exit(1);
         }
     }
 
-    pub fn validate(&self) {
+    /// Problems that prevent the config from being used, once merged with the defaults.
+    pub fn check(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        let mut report = |key: &str, message: &str| {
+            problems.push(Problem {
+                key: key.to_string(),
+                message: message.to_string(),
+            })
+        };
+
         if self.reminder.threshold <= self.warn.threshold {
-            error!("reminder threshold must be higher than warn threshold");
-            process::exit(1);
+            report(
+                "reminder.threshold",
+                "reminder threshold must be higher than warn threshold",
+            );
         }
 
         if self.warn.threshold <= self.threat.threshold {
-            error!("warn threshold must be higher than threat threshold");
-            process::exit(1);
+            report(
+                "warn.threshold",
+                "warn threshold must be higher than threat threshold",
+            );
+        }
+
+        if self.peripherals.enabled
+            && self.peripherals.warn.threshold <= self.peripherals.threat.threshold
+        {
+            report(
+                "peripherals.warn.threshold",
+                "peripherals warn threshold must be higher than peripherals threat threshold",
+            );
+        }
+
+        // Sinks that can't work without a setting.
+        let unset_sinks = [
+            (
+                SinkKind::Webhook,
+                "sinks.webhook.url",
+                self.sinks.webhook.url.is_empty(),
+            ),
+            (
+                SinkKind::Command,
+                "sinks.command.run",
+                self.sinks.command.run.is_empty(),
+            ),
+        ];
+        let routed_sinks = self.get_routed_sinks();
+
+        for (kind, key, unset) in unset_sinks {
+            if unset && routed_sinks.contains(&kind) {
+                report(
+                    key,
+                    &format!("{} sink is enabled but '{}' is empty", kind.name(), key),
+                );
+            }
+        }
+
+        if self.polling.min_interval_ms > self.polling.max_interval_ms {
+            report(
+                "polling.min_interval_ms",
+                "polling min interval must not be higher than polling max interval",
+            );
+        }
+
+        for (key, percent) in [
+            ("backlight.warn_percent", self.backlight.warn_percent),
+            ("backlight.threat_percent", self.backlight.threat_percent),
+        ] {
+            if percent > 100 {
+                report(
+                    key,
+                    &format!("backlight dim percentage {} is above 100", percent),
+                );
+            }
+        }
+
+        if let Err(error) = self.charge_control.get_thresholds().check() {
+            let key = if self.charge_control.end_threshold > 100 {
+                "charge_control.end_threshold"
+            } else {
+                "charge_control.start_threshold"
+            };
+            report(key, &error);
+        }
+
+        for (key, profile) in [
+            ("power_profiles.reminder", &self.power_profiles.reminder),
+            ("power_profiles.warn", &self.power_profiles.warn),
+            ("power_profiles.threat", &self.power_profiles.threat),
+        ] {
+            if !profile.is_empty() && !POWER_PROFILES.contains(&profile.as_str()) {
+                report(
+                    key,
+                    &format!(
+                        "unknown power profile '{}', expected one of {}",
+                        profile,
+                        POWER_PROFILES.join(", ")
+                    ),
+                );
+            }
+        }
+
+        for (name, profile) in &self.profiles {
+            let config = self.with_profile(profile);
+            // Points at the threshold the profile sets, the higher one if both.
+            let get_key = |higher: &str, set: bool, lower: &str| {
+                format!(
+                    "profiles.{name}.{}.threshold",
+                    if set { higher } else { lower }
+                )
+            };
+
+            if config.reminder.threshold <= config.warn.threshold {
+                report(
+                    &get_key("reminder", profile.reminder.threshold != 0, "warn"),
+                    &format!("profile '{name}' reminder threshold must be higher than its warn threshold"),
+                );
+            }
+
+            if config.warn.threshold <= config.threat.threshold {
+                report(
+                    &get_key("warn", profile.warn.threshold != 0, "threat"),
+                    &format!(
+                        "profile '{name}' warn threshold must be higher than its threat threshold"
+                    ),
+                );
+            }
+
+            // Sinks the base config already routes to were reported above.
+            for (level, bound) in [
+                ("reminder", &profile.reminder),
+                ("warn", &profile.warn),
+                ("threat", &profile.threat),
+            ] {
+                for (kind, key, unset) in unset_sinks {
+                    if unset && bound.sinks.contains(&kind) && !routed_sinks.contains(&kind) {
+                        report(
+                            &format!("profiles.{name}.{level}.sinks"),
+                            &format!(
+                                "profile '{name}' sends {level} to the {} sink but '{}' is empty",
+                                kind.name(),
+                                key
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+
+        for rule in &self.profile_rules {
+            if !self.profiles.contains_key(&rule.profile) {
+                report(
+                    "profile_rules",
+                    &format!(
+                        "profile rule selects the unknown profile '{}'",
+                        rule.profile
+                    ),
+                );
+            }
+
+            if let Some(between) = &rule.between {
+                if let Err(error) = between.parse::<TimeWindow>() {
+                    report("profile_rules", &error);
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// The config with the settings of `profile` in place of the base ones.
+    pub fn with_profile(&self, profile: &Profile) -> Config {
+        Config {
+            reminder: profile.reminder.or(&self.reminder),
+            warn: profile.warn.or(&self.warn),
+            threat: profile.threat.or(&self.threat),
+            sound: profile.sound.unwrap_or(self.sound),
+            repeat_s: profile.repeat_s.unwrap_or(self.repeat_s),
+            ..self.clone()
+        }
+    }
+
+    /// The notification level of the given battery capacity.
+    pub fn get_notification_level(&self, capacity: u8) -> BatteryNotificationLevel {
+        match capacity {
+            c if c > self.warn.threshold && c <= self.reminder.threshold => {
+                BatteryNotificationLevel::Reminder
+            }
+            c if c > self.threat.threshold && c <= self.warn.threshold => {
+                BatteryNotificationLevel::Warn
+            }
+            c if c <= self.threat.threshold => BatteryNotificationLevel::Threat,
+            _ => BatteryNotificationLevel::NoConflict,
+        }
+    }
+
+    /// Sinks that should receive a notification declaring the given overrides.
+    pub fn sinks_for<'a>(&'a self, sinks: &'a [SinkKind]) -> &'a [SinkKind] {
+        if sinks.is_empty() {
+            &self.sinks.enabled
+        } else {
+            sinks
         }
     }
 
-    fn merge(mut self, other: Config) -> Config {
+    /// Sinks an event of the system service goes to, as if it was notified here.
+    pub fn get_event_sinks(&self, event: &Event) -> &[SinkKind] {
+        let sinks = match event.kind {
+            EventKind::Reminder => &self.reminder.sinks,
+            EventKind::Warn => &self.warn.sinks,
+            EventKind::Threat => &self.threat.sinks,
+            EventKind::Unplug if self.unplug.enabled => &self.unplug.sinks,
+            EventKind::NotCharging if self.not_charging.enabled => &self.not_charging.sinks,
+            EventKind::Suspend if self.sleep.warning.enabled => &self.sleep.warning.sinks,
+            EventKind::Peripheral if event.level == BatteryNotificationLevel::Warn => {
+                &self.peripherals.warn.sinks
+            }
+            EventKind::Peripheral => &self.peripherals.threat.sinks,
+            EventKind::Test => return &self.sinks.enabled,
+            _ => return &[],
+        };
+
+        self.sinks_for(sinks)
+    }
+
+    /// Every sink that some notification may be routed to, whatever the active profile.
+    pub fn used_sinks(&self) -> Vec<SinkKind> {
+        let mut kinds = self.get_routed_sinks();
+        for profile in self.profiles.values() {
+            for kind in self.with_profile(profile).get_routed_sinks() {
+                if !kinds.contains(&kind) {
+                    kinds.push(kind)
+                }
+            }
+        }
+
+        kinds
+    }
+
+    // Sinks the notifications of this config are routed to, profiles aside.
+    fn get_routed_sinks(&self) -> Vec<SinkKind> {
+        let overrides = [
+            &self.reminder.sinks,
+            &self.warn.sinks,
+            &self.threat.sinks,
+            &self.unplug.sinks,
+            &self.not_charging.sinks,
+            &self.sleep.warning.sinks,
+            &self.peripherals.warn.sinks,
+            &self.peripherals.threat.sinks,
+        ];
+
+        let mut kinds: Vec<SinkKind> = Vec::new();
+        for sinks in overrides {
+            for kind in self.sinks_for(sinks) {
+                if !kinds.contains(kind) {
+                    kinds.push(*kind)
+                }
+            }
+        }
+
+        kinds
+    }
+
+    pub fn merge(mut self, other: Config) -> Config {
         let warn_if_not_zero = |threshold: u8, label: &str| {
             if threshold != 0 {
                 warn!(
@@ -118,71 +1063,169 @@ impl Config {
             }
         };
 
-        if self.reminder.threshold <= 0 || self.reminder.threshold > 100 {
+        if self.reminder.threshold == 0 || self.reminder.threshold > 100 {
             warn_if_not_zero(self.reminder.threshold, "reminder");
             self.reminder.threshold = other.reminder.threshold
         }
 
-        if self.reminder.title == "" {
+        if self.reminder.title.is_empty() {
             self.reminder.title = other.reminder.title
         }
 
-        if self.reminder.content == "" {
+        if self.reminder.content.is_empty() {
             self.reminder.content = other.reminder.content
         }
 
-        if self.threat.threshold <= 0 || self.threat.threshold > 100 {
+        if self.threat.threshold == 0 || self.threat.threshold > 100 {
             warn_if_not_zero(self.threat.threshold, "threat");
             self.threat.threshold = other.threat.threshold
         }
 
-        if self.threat.title == "" {
+        if self.threat.title.is_empty() {
             self.threat.title = other.threat.title
         }
 
-        if self.threat.content == "" {
+        if self.threat.content.is_empty() {
             self.threat.content = other.threat.content
         }
 
-        if self.warn.threshold <= 0 || self.warn.threshold > 100 {
+        if self.warn.threshold == 0 || self.warn.threshold > 100 {
             warn_if_not_zero(self.warn.threshold, "warn");
             self.warn.threshold = other.warn.threshold
         }
 
-        if self.warn.title == "" {
+        if self.warn.title.is_empty() {
             self.warn.title = other.warn.title
         }
 
-        if self.warn.content == "" {
+        if self.warn.content.is_empty() {
             self.warn.content = other.warn.content
         }
 
-        if self.interval_ms <= 0 {
+        if self.unplug.title.is_empty() {
+            self.unplug.title = other.unplug.title
+        }
+
+        if self.unplug.content.is_empty() {
+            self.unplug.content = other.unplug.content
+        }
+
+        if self.not_charging.title.is_empty() {
+            self.not_charging.title = other.not_charging.title
+        }
+
+        if self.not_charging.content.is_empty() {
+            self.not_charging.content = other.not_charging.content
+        }
+
+        if self.not_charging.capacity_drop == 0 {
+            self.not_charging.capacity_drop = other.not_charging.capacity_drop
+        }
+
+        if self.not_charging.grace_s == 0 {
+            self.not_charging.grace_s = other.not_charging.grace_s
+        }
+
+        if self.not_charging.charge_limit == 0 || self.not_charging.charge_limit > 100 {
+            warn_if_not_zero(self.not_charging.charge_limit, "not_charging.charge_limit");
+            self.not_charging.charge_limit = other.not_charging.charge_limit
+        }
+
+        if self.not_charging.min_adapter_watts.is_none() {
+            self.not_charging.min_adapter_watts = other.not_charging.min_adapter_watts
+        }
+
+        if self.peripherals.warn.threshold == 0 || self.peripherals.warn.threshold > 100 {
+            warn_if_not_zero(self.peripherals.warn.threshold, "peripherals.warn");
+            self.peripherals.warn.threshold = other.peripherals.warn.threshold
+        }
+
+        if self.peripherals.warn.title.is_empty() {
+            self.peripherals.warn.title = other.peripherals.warn.title
+        }
+
+        if self.peripherals.warn.content.is_empty() {
+            self.peripherals.warn.content = other.peripherals.warn.content
+        }
+
+        if self.peripherals.threat.threshold == 0 || self.peripherals.threat.threshold > 100 {
+            warn_if_not_zero(self.peripherals.threat.threshold, "peripherals.threat");
+            self.peripherals.threat.threshold = other.peripherals.threat.threshold
+        }
+
+        if self.peripherals.threat.title.is_empty() {
+            self.peripherals.threat.title = other.peripherals.threat.title
+        }
+
+        if self.peripherals.threat.content.is_empty() {
+            self.peripherals.threat.content = other.peripherals.threat.content
+        }
+
+        if self.interval_ms == 0 {
             self.interval_ms = other.interval_ms
         }
 
+        if self.sinks.enabled.is_empty() {
+            self.sinks.enabled = other.sinks.enabled
+        }
+
+        if self.sinks.webhook.timeout_ms == 0 {
+            self.sinks.webhook.timeout_ms = other.sinks.webhook.timeout_ms
+        }
+
+        if self.sinks.command.timeout_ms == 0 {
+            self.sinks.command.timeout_ms = other.sinks.command.timeout_ms
+        }
+
+        if self.hooks.timeout_ms == 0 {
+            self.hooks.timeout_ms = other.hooks.timeout_ms
+        }
+
+        if self.charge_control.helper.is_empty() {
+            self.charge_control.helper = other.charge_control.helper
+        }
+
+        if self.sleep.warn_ms == 0 {
+            self.sleep.warn_ms = other.sleep.warn_ms
+        }
+
+        if self.sleep.warning.title.is_empty() {
+            self.sleep.warning.title = other.sleep.warning.title
+        }
+
+        if self.sleep.warning.content.is_empty() {
+            self.sleep.warning.content = other.sleep.warning.content
+        }
+
+        if self.polling.min_interval_ms == 0 {
+            self.polling.min_interval_ms = other.polling.min_interval_ms
+        }
+
+        if self.polling.max_interval_ms == 0 {
+            self.polling.max_interval_ms = other.polling.max_interval_ms
+        }
+
         self
     }
 }
 
-pub fn get_config_file(file_path: Option<String>) -> String {
-    file_path.unwrap_or_else(|| {
-        let config_path = match env::var("XDG_CONFIG_FILE") {
-            Ok(p) => String::from(p),
-            Err(_) => {
-                let fallback_path = Path::new(&env::var("HOME").unwrap())
-                    .join(".config")
-                    .to_owned();
+// The names a config file is looked up by in a config directory, any format
+// works but TOML is the one created when there's none.
+const CONFIG_FILE_NAMES: [&str; 4] = ["config.toml", "config.yaml", "config.yml", "config.json"];
 
-                fallback_path.to_string_lossy().to_string()
-            }
-        };
+pub fn get_config_file_in(dir: &Path) -> PathBuf {
+    CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| dir.join(CONFIG_FILE_NAMES[0]))
+}
 
-        return Path::new(config_path.as_str())
-            .join("battery-notifier")
-            .join("config.toml")
-            .to_str()
-            .unwrap()
-            .to_owned();
-    })
+// The user config file, which doesn't need to exist.
+pub fn get_config_file(file_path: Option<String>) -> Result<String, XdgError> {
+    match file_path {
+        Some(path) => Ok(path),
+        None => get_app_dir(BaseDir::Config)
+            .map(|dir| get_config_file_in(&dir).to_string_lossy().into_owned()),
+    }
 }
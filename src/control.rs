@@ -0,0 +1,223 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    charge_control::{set_thresholds, ChargeThresholds},
+    xdg::{get_app_dir, get_system_runtime_dir, BaseDir, XdgError},
+};
+
+// The system service listens in its own runtime directory, only root can
+// reach it there.
+pub fn get_socket_path(system: bool) -> Result<PathBuf, XdgError> {
+    if system {
+        return Ok(get_system_runtime_dir().join("control.sock"));
+    }
+
+    get_app_dir(BaseDir::Runtime).map(|dir| dir.join("control.sock"))
+}
+
+#[derive(Default)]
+struct State {
+    profiles: Vec<String>,
+    // Runs `charge-control-helper` when the daemon can't write the thresholds.
+    helper: String,
+    manual_profile: Option<String>,
+    active_profile: Option<String>,
+}
+
+// Requests are one line of text answered by one line starting with `ok` or
+// `error`. They are served from a thread so `ctl` doesn't wait for the next
+// battery check, the loop picks the changes up on its next iteration.
+#[derive(Clone)]
+pub struct Control {
+    state: Arc<Mutex<State>>,
+    // Battery whose charge thresholds `charge-limit` sets.
+    battery: PathBuf,
+}
+
+impl Control {
+    pub fn start(
+        path: &Path,
+        profiles: Vec<String>,
+        battery: PathBuf,
+        helper: String,
+    ) -> io::Result<Self> {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another instance is listening",
+            ));
+        }
+
+        // Left behind by an instance that didn't exit cleanly.
+        let _ = fs::remove_file(path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        let control = Control {
+            state: Arc::new(Mutex::new(State {
+                profiles,
+                helper,
+                ..Default::default()
+            })),
+            battery,
+        };
+
+        // Each client gets a thread, one waiting for the helper or sending
+        // nothing must not hold the others back.
+        let server = control.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let server = server.clone();
+                thread::spawn(move || server.serve(stream));
+            }
+        });
+
+        Ok(control)
+    }
+
+    fn serve(&self, stream: UnixStream) {
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+
+        let mut request = String::new();
+        if BufReader::new(&stream).read_line(&mut request).is_ok() {
+            let _ = writeln!(&stream, "{}", self.handle(request.trim()));
+        }
+    }
+
+    fn handle(&self, request: &str) -> String {
+        let words: Vec<&str> = request.split_whitespace().collect();
+
+        // The helper may wait for a password, the state isn't locked meanwhile.
+        if words.first() == Some(&"charge-limit") {
+            return self.handle_charge_limit(&words[1..]);
+        }
+
+        let mut state = self.state.lock().expect("lock control state");
+        match words.as_slice() {
+            ["profile"] => match (&state.manual_profile, &state.active_profile) {
+                (Some(name), _) => format!("ok {} (selected with ctl)", name),
+                (None, Some(name)) => format!("ok {} (selected by the rules)", name),
+                (None, None) => "ok no profile is active".to_string(),
+            },
+            ["profile", "auto"] => {
+                state.manual_profile = None;
+                "ok profiles are selected by the rules again".to_string()
+            }
+            ["profile", name] if state.profiles.iter().any(|p| p == name) => {
+                state.manual_profile = Some(name.to_string());
+                format!("ok {} is active until `ctl profile auto`", name)
+            }
+            ["profile", name] => format!("error unknown profile '{}'", name),
+            _ => format!("error invalid request '{}'", request),
+        }
+    }
+
+    fn handle_charge_limit(&self, words: &[&str]) -> String {
+        let parse = |word: &str| match word {
+            "-" => Ok(None),
+            word => word
+                .parse::<u8>()
+                .map(Some)
+                .map_err(|_| format!("error invalid threshold '{}'", word)),
+        };
+
+        let thresholds = match words {
+            [] => return format!("ok {}", ChargeThresholds::read(&self.battery)),
+            [start, end] => match (parse(start), parse(end)) {
+                (Ok(start), Ok(end)) => ChargeThresholds { start, end },
+                (Err(error), _) | (_, Err(error)) => return error,
+            },
+            _ => return format!("error invalid request 'charge-limit {}'", words.join(" ")),
+        };
+
+        let helper = self
+            .state
+            .lock()
+            .expect("lock control state")
+            .helper
+            .clone();
+        match set_thresholds(&self.battery, thresholds, &helper) {
+            Ok(()) => format!("ok {}", ChargeThresholds::read(&self.battery)),
+            Err(error) => format!("error {}", error),
+        }
+    }
+
+    pub fn get_manual_profile(&self) -> Option<String> {
+        self.state
+            .lock()
+            .expect("lock control state")
+            .manual_profile
+            .clone()
+    }
+
+    // Profiles of a reloaded config, a selected one that went away is dropped.
+    pub fn set_profiles(&self, profiles: Vec<String>) {
+        let mut state = self.state.lock().expect("lock control state");
+        if let Some(name) = &state.manual_profile {
+            if !profiles.contains(name) {
+                state.manual_profile = None;
+            }
+        }
+        state.profiles = profiles;
+    }
+
+    pub fn set_helper(&self, helper: String) {
+        self.state.lock().expect("lock control state").helper = helper;
+    }
+
+    pub fn set_active_profile(&self, name: Option<&str>) {
+        self.state
+            .lock()
+            .expect("lock control state")
+            .active_profile = name.map(str::to_owned);
+    }
+}
+
+// Sends `request` to the running daemon and returns its answer, waiting for
+// it at most `timeout`.
+pub fn send_request(request: &str, timeout: Duration, system: bool) -> Result<String, String> {
+    let path = get_socket_path(system).map_err(|e| e.to_string())?;
+    let unreachable =
+        |e: io::Error| format!("unable to reach the daemon at {}: {}", path.display(), e);
+
+    let mut stream = UnixStream::connect(&path).map_err(unreachable)?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .map_err(unreachable)?;
+    writeln!(stream, "{}", request).map_err(unreachable)?;
+
+    let mut response = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut response)
+        .map_err(unreachable)?;
+
+    match response.trim().split_once(' ') {
+        Some(("ok", message)) => Ok(message.to_string()),
+        Some(("error", message)) => Err(message.to_string()),
+        _ => Err(format!("unexpected answer '{}'", response.trim())),
+    }
+}
+
+// Runs a `ctl` subcommand and returns the exit code.
+pub fn run_ctl(request: &str, timeout: Duration, system: bool) -> i32 {
+    match send_request(request, timeout, system) {
+        Ok(message) => {
+            println!("{}", message);
+            0
+        }
+        Err(error) => {
+            eprintln!("error: {}", error);
+            1
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{clock::Clock, config::SinkKind, event::Event, notify::Sound};
+
+// Something the daemon would have done, printed instead of doing it.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action<'a> {
+    Notification {
+        sinks: &'a [SinkKind],
+        event: &'a Event,
+    },
+    Dismiss,
+    Sound {
+        sound: Sound,
+    },
+    Hook {
+        hook: &'a str,
+        command: &'a str,
+        env: BTreeMap<&'static str, String>,
+    },
+    PowerProfile {
+        profile: &'a str,
+    },
+    Brightness {
+        brightness: u32,
+    },
+    ChargeThresholds {
+        start: Option<u8>,
+        end: Option<u8>,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    // Milliseconds since the start, deterministic under `--speed virtual`.
+    at_ms: u128,
+    #[serde(flatten)]
+    action: Action<'a>,
+}
+
+// Prints every action as a JSON line on stdout, logs keep going to stderr so
+// the output can be compared against a snapshot as is.
+#[derive(Clone)]
+pub struct DryRun {
+    clock: Clock,
+}
+
+impl DryRun {
+    pub fn new(clock: Clock) -> Self {
+        Self { clock }
+    }
+
+    pub fn print(&self, action: Action) {
+        let record = Record {
+            at_ms: self.clock.now().as_millis(),
+            action,
+        };
+
+        println!(
+            "{}",
+            serde_json::to_string(&record).expect("serialize dry run action")
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::{battery::BatteryNotificationLevel, notify::Urgency};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Reminder,
+    Warn,
+    Threat,
+    Plug,
+    Unplug,
+    Full,
+    NotCharging,
+    Peripheral,
+    Suspend,
+    Test,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Reminder => "reminder",
+            EventKind::Warn => "warn",
+            EventKind::Threat => "threat",
+            EventKind::Plug => "plug",
+            EventKind::Unplug => "unplug",
+            EventKind::Full => "full",
+            EventKind::NotCharging => "not_charging",
+            EventKind::Peripheral => "peripheral",
+            EventKind::Suspend => "suspend",
+            EventKind::Test => "test",
+        }
+    }
+
+    pub fn from_level(level: BatteryNotificationLevel) -> Option<EventKind> {
+        match level {
+            BatteryNotificationLevel::Reminder => Some(EventKind::Reminder),
+            BatteryNotificationLevel::Warn => Some(EventKind::Warn),
+            BatteryNotificationLevel::Threat => Some(EventKind::Threat),
+            _ => None,
+        }
+    }
+}
+
+// Everything a sink needs to know to report a battery notification, so desktop
+// popups, webhooks and external commands all render the same data.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Event {
+    pub kind: EventKind,
+    pub level: BatteryNotificationLevel,
+    pub urgency: Urgency,
+    pub capacity: u8,
+    pub status: String,
+    pub title: String,
+    pub content: String,
+    // Power supply node of the peripheral, None for the system battery.
+    pub device: Option<String>,
+}
+
+impl Event {
+    pub fn env_vars(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("BATTERY_EVENT", self.kind.name().to_string()),
+            ("BATTERY_LEVEL", self.level.name().to_string()),
+            ("BATTERY_URGENCY", self.urgency.to_string()),
+            ("BATTERY_CAPACITY", self.capacity.to_string()),
+            ("BATTERY_STATUS", self.status.to_owned()),
+            ("BATTERY_TITLE", self.title.to_owned()),
+            ("BATTERY_CONTENT", self.content.to_owned()),
+            ("BATTERY_DEVICE", self.device.to_owned().unwrap_or_default()),
+        ]
+    }
+}
+
+// Values available to the `${{name}}` placeholders of notification templates.
+pub fn template_vars(
+    capacity: u8,
+    time_remaining: Option<Duration>,
+) -> Vec<(&'static str, String)> {
+    vec![
+        ("capacity", capacity.to_string()),
+        ("time_remaining", format_duration(time_remaining)),
+    ]
+}
+
+pub fn format_duration(duration: Option<Duration>) -> String {
+    match duration {
+        Some(d) => {
+            let minutes = d.as_secs() / 60;
+            format!("{}h {:02}m", minutes / 60, minutes % 60)
+        }
+        None => "unknown".to_string(),
+    }
+}
@@ -0,0 +1,131 @@
+use log::{debug, error, info, warn};
+use std::{
+    io,
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    battery::BatteryNotificationLevel,
+    config::HooksConfig,
+    dry_run::{Action, DryRun},
+    event::EventKind,
+};
+
+pub struct Hooks {
+    config: HooksConfig,
+    dry_run: Option<DryRun>,
+}
+
+impl Hooks {
+    pub fn new(config: HooksConfig, dry_run: Option<DryRun>) -> Self {
+        Self { config, dry_run }
+    }
+
+    fn get_command(&self, kind: EventKind) -> &str {
+        match kind {
+            EventKind::Reminder => &self.config.on_reminder,
+            EventKind::Warn => &self.config.on_warn,
+            EventKind::Threat => &self.config.on_threat,
+            EventKind::Plug => &self.config.on_plug,
+            EventKind::Unplug => &self.config.on_unplug,
+            EventKind::Full => &self.config.on_full,
+            EventKind::NotCharging => &self.config.on_not_charging,
+            EventKind::Peripheral => &self.config.on_peripheral,
+            EventKind::Suspend => &self.config.on_suspend,
+            EventKind::Test => "",
+        }
+    }
+
+    pub fn run(
+        &self,
+        kind: EventKind,
+        capacity: u8,
+        status: &str,
+        level: BatteryNotificationLevel,
+        device: Option<&str>,
+    ) {
+        let command = self.get_command(kind);
+        if command.is_empty() {
+            return;
+        }
+
+        let hook = format!("on_{}", kind.name());
+
+        let env = vec![
+            ("BATTERY_HOOK", hook.to_owned()),
+            ("BATTERY_CAPACITY", capacity.to_string()),
+            ("BATTERY_STATUS", status.to_owned()),
+            ("BATTERY_LEVEL", level.name().to_string()),
+            ("BATTERY_DEVICE", device.unwrap_or_default().to_string()),
+        ];
+
+        if let Some(dry_run) = &self.dry_run {
+            return dry_run.print(Action::Hook {
+                hook: &hook,
+                command,
+                env: env.into_iter().collect(),
+            });
+        }
+
+        info!("running {} hook: {}", hook, command);
+
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        if let Err(error) = spawn_command(command, env, timeout) {
+            error!("unable to run {} hook: {}", hook, error)
+        }
+    }
+}
+
+// Runs `command` through `sh -c` without waiting for it. A watcher thread reaps the
+// process and kills its whole process group once `timeout` is exceeded, so a hung
+// script can neither stall the battery loop nor linger forever.
+pub fn spawn_command(
+    command: &str,
+    env: Vec<(&'static str, String)>,
+    timeout: Duration,
+) -> io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env)
+        .stdin(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+
+    let command = command.to_owned();
+    let started_at = Instant::now();
+
+    thread::spawn(move || loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                if status.success() {
+                    debug!("`{}` finished successfully", command)
+                } else {
+                    warn!("`{}` exited with {}", command, status)
+                }
+
+                return;
+            }
+            Ok(None) if started_at.elapsed() >= timeout => {
+                warn!("`{}` timed out after {:?}, killing it", command, timeout);
+
+                // The child is the leader of its own process group, so this also
+                // takes down whatever the shell started.
+                unsafe { libc::kill(-(child.id() as i32), libc::SIGKILL) };
+                let _ = child.wait();
+
+                return;
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(100)),
+            Err(error) => {
+                error!("error waiting for `{}`: {}", command, error);
+                return;
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use log::{debug, info, warn};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+use zbus::zvariant::OwnedFd;
+
+use crate::logind::Logind;
+
+// Holds a logind delay lock on sleep so a suspend waits for the daemon to
+// act on a critical battery, logind gives up waiting after its
+// `InhibitDelayMaxSec` anyway. The lock is dropped once the daemon is done
+// and taken again after the resume.
+pub struct SleepInhibitor {
+    logind: Logind,
+    lock: Option<OwnedFd>,
+}
+
+impl SleepInhibitor {
+    pub fn new(logind: Logind) -> Self {
+        Self { logind, lock: None }
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    pub fn acquire(&mut self) {
+        if self.is_held() {
+            return;
+        }
+
+        match self.logind.inhibit(
+            "sleep",
+            "battery-notifier",
+            "Acting on a critical battery before suspending",
+            "delay",
+        ) {
+            Ok(lock) => {
+                debug!("holding a logind delay lock on sleep");
+                self.lock = Some(lock);
+            }
+            Err(error) => warn!("unable to take a logind delay lock on sleep: {}", error),
+        }
+    }
+
+    // Closing the descriptor lets the pending suspend go on.
+    pub fn release(&mut self) {
+        if self.lock.take().is_some() {
+            debug!("released the logind delay lock on sleep");
+        }
+    }
+}
+
+// How often the lid is looked at while its switch is blocked.
+const LID_POLL: Duration = Duration::from_secs(1);
+
+// logind refuses to hibernate while a suspend is pending, so a suspend can't
+// be turned into a hibernation once it started. At a critical level the lid
+// switch is blocked instead, and the daemon hibernates itself when the lid
+// closes where logind would have suspended.
+pub struct LidHibernator {
+    logind: Logind,
+    armed: bool,
+    lock: Option<OwnedFd>,
+    // Stops the thread watching the lid for the current lock.
+    stop: Arc<AtomicBool>,
+}
+
+impl LidHibernator {
+    pub fn new(logind: Logind) -> Self {
+        Self {
+            logind,
+            armed: false,
+            lock: None,
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn arm(&mut self) {
+        if self.armed {
+            return;
+        }
+        self.armed = true;
+
+        match self.logind.get_lid_switch_action() {
+            Ok(action) if action == "suspend" => {}
+            Ok(action) => {
+                info!(
+                    "closing the lid doesn't suspend ({}), it's left to logind",
+                    action
+                );
+                return;
+            }
+            Err(error) => {
+                warn!("unable to read what closing the lid does: {}", error);
+                return;
+            }
+        }
+
+        match self.logind.inhibit(
+            "handle-lid-switch",
+            "battery-notifier",
+            "Hibernating instead of suspending at a critical battery",
+            "block",
+        ) {
+            Ok(lock) => {
+                info!("closing the lid hibernates until the battery recovers");
+                self.lock = Some(lock);
+            }
+            Err(error) => {
+                warn!(
+                    "unable to take a logind block lock on the lid switch: {}",
+                    error
+                );
+                return;
+            }
+        }
+
+        self.stop = Arc::new(AtomicBool::new(false));
+        let stop = self.stop.clone();
+        let logind = self.logind.clone();
+        thread::spawn(move || watch_lid(&logind, &stop));
+    }
+
+    pub fn disarm(&mut self) {
+        if !self.armed {
+            return;
+        }
+        self.armed = false;
+
+        self.stop.store(true, Ordering::Relaxed);
+        if self.lock.take().is_some() {
+            info!("closing the lid is left to logind again");
+        }
+    }
+}
+
+// A replaced hibernator must not leave its thread watching the lid.
+impl Drop for LidHibernator {
+    fn drop(&mut self) {
+        self.disarm()
+    }
+}
+
+fn watch_lid(logind: &Logind, stop: &AtomicBool) {
+    let is_closed = || logind.is_lid_closed().unwrap_or(false);
+    let mut closed = is_closed();
+
+    loop {
+        thread::sleep(LID_POLL);
+        if stop.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let was_closed = closed;
+        closed = is_closed();
+        if !closed || was_closed {
+            continue;
+        }
+
+        // logind leaves the lid alone while docked by default.
+        if logind.is_docked().unwrap_or(false) {
+            info!("the lid closed while docked, not hibernating");
+            continue;
+        }
+
+        warn!("the lid closed at a critical battery, hibernating");
+        if let Err(error) = logind.hibernate() {
+            warn!("unable to hibernate, suspending instead: {}", error);
+            if let Err(error) = logind.suspend() {
+                warn!("unable to suspend: {}", error);
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use clap::CommandFactory;
+use log::{debug, error, info, warn};
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, env, fmt, fs, path::Path};
+
+use crate::{
+    cli::Args,
+    config::{get_config_file_in, Config, Format},
+    schema::render_toml,
+    xdg::get_config_dirs,
+};
+
+pub const SYSTEM_CONFIG_FILE: &str = "/etc/battery-notifier/config.toml";
+pub const ENV_PREFIX: &str = "BATTERY_NOTIFIER_";
+
+// Where a config value comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Default,
+    File(String),
+    Env(String),
+    Cli(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "{}", path),
+            Source::Env(name) => write!(f, "env {}", name),
+            Source::Cli(flag) => write!(f, "{}", flag),
+        }
+    }
+}
+
+// Every layer is merged key by key into the previous ones, the last one to set
+// a key wins. Layers that can't be parsed are skipped with an error.
+pub struct LayeredConfig {
+    pub config: Config,
+    // The merged layers, without the defaults.
+    pub layered: Value,
+    // Dotted path of every value set by a layer.
+    pub origins: BTreeMap<String, Source>,
+    // Layers skipped and the merged config replaced by the defaults, already logged.
+    pub errors: Vec<String>,
+}
+
+impl LayeredConfig {
+    pub fn load(user_file: Option<&str>, overrides: Vec<(String, Value, Source)>) -> Self {
+        let mut layered = Value::Object(Map::new());
+        let mut origins = BTreeMap::new();
+        let mut errors = Vec::new();
+        let mut add = |value: Value, source: Source| {
+            merge_value(&mut layered, value, "", &source, &mut origins)
+        };
+
+        for path in get_config_files(user_file) {
+            match read_layer(&path) {
+                Ok(Some(value)) => {
+                    info!("loaded config layer {}", path);
+                    add(value, Source::File(path))
+                }
+                Ok(None) => {}
+                Err(message) => {
+                    error!("{}", message);
+                    errors.push(message)
+                }
+            }
+        }
+
+        for (name, path, value) in get_env_overrides() {
+            add(nest(&path, value), Source::Env(name))
+        }
+
+        for (path, value, source) in overrides {
+            add(nest(&path, value), source)
+        }
+
+        let config = match serde_json::from_value::<Config>(layered.clone()) {
+            Ok(config) => config.merge(Config::default()),
+            Err(error) => {
+                let message = format!("unable to use the merged config: {}", error);
+                error!("{}", message);
+                info!("default config will be used");
+                errors.push(message);
+                Config::default()
+            }
+        };
+
+        Self {
+            config,
+            layered,
+            origins,
+            errors,
+        }
+    }
+
+    // Where the effective value at `path` comes from. Values a layer set but
+    // that were replaced because they are invalid count as defaults.
+    pub fn get_origin(&self, path: &str, effective: &Value) -> Source {
+        match self.origins.get(path) {
+            Some(source) if lookup(&self.layered, path) == Some(effective) => source.clone(),
+            _ => Source::Default,
+        }
+    }
+}
+
+// The merged layers as TOML, every value commented with the layer it comes
+// from. With `effective`, the defaults fill in what no layer sets.
+pub fn dump_config(layers: &LayeredConfig, effective: bool) -> String {
+    let mut out = String::new();
+
+    if effective {
+        let values = serde_json::to_value(&layers.config).expect("serialize config");
+        render_toml(&mut out, &values, "", &|path, value| {
+            (!value.is_object()).then(|| format!("from {}", layers.get_origin(path, value)))
+        });
+    } else {
+        render_toml(&mut out, &layers.layered, "", &|path, _| {
+            layers
+                .origins
+                .get(path)
+                .map(|source| format!("from {}", source))
+        });
+    }
+
+    out.trim_start().to_string()
+}
+
+// The system file, the XDG config dirs from the least important, the user
+// file, then the drop-ins next to the user file in lexical order so they can
+// be numbered like systemd ones.
+fn get_config_files(user_file: Option<&str>) -> Vec<String> {
+    let mut files = vec![SYSTEM_CONFIG_FILE.to_string()];
+    files.extend(
+        get_config_dirs()
+            .iter()
+            .rev()
+            .map(|dir| get_config_file_in(dir).to_string_lossy().into_owned()),
+    );
+
+    let Some(user_file) = user_file else {
+        return files;
+    };
+    files.push(user_file.to_string());
+
+    let drop_ins = Path::new(user_file)
+        .parent()
+        .map(|dir| dir.join("config.d"))
+        .and_then(|dir| fs::read_dir(dir).ok());
+
+    if let Some(entries) = drop_ins {
+        let mut drop_ins: Vec<String> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                matches!(
+                    path.extension().and_then(|e| e.to_str()),
+                    Some("toml" | "yaml" | "yml" | "json")
+                )
+            })
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+
+        drop_ins.sort();
+        files.extend(drop_ins);
+    }
+
+    files
+}
+
+fn read_layer(path: &str) -> Result<Option<Value>, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(error) => {
+            debug!("skipping config layer {}: {}", path, error);
+            return Ok(None);
+        }
+    };
+
+    // Deserializing a Config first reports type errors with their position.
+    let format = Format::from_path(path);
+    let result = format
+        .deserialize::<Config>(&content)
+        .and_then(|_| format.deserialize::<Value>(&content));
+
+    result
+        .map(Some)
+        .map_err(|error| format!("unable to parse config layer {}: {}", path, error))
+}
+
+// Variables under the prefix that belong to the command line, not the config,
+// as declared by the `env` attributes of the flags.
+fn get_cli_env_vars() -> Vec<String> {
+    Args::command()
+        .get_arguments()
+        .filter_map(|arg| arg.get_env())
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect()
+}
+
+// `BATTERY_NOTIFIER_WARN__THRESHOLD=20` sets `warn.threshold`, a double
+// underscore separating the keys since they contain single ones.
+fn get_env_overrides() -> Vec<(String, String, Value)> {
+    let cli_vars = get_cli_env_vars();
+    let mut overrides: Vec<(String, String, Value)> = env::vars()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX) && !cli_vars.contains(name))
+        .filter_map(|(name, raw)| {
+            let path = name[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
+
+            match check_override(&path, parse_value(&raw)) {
+                Ok(value) => Some((name, path, value)),
+                Err(error) => {
+                    warn!("ignoring {}: {}", name, error);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    overrides.sort_by(|a, b| a.0.cmp(&b.0));
+    overrides
+}
+
+// Makes sure a single value set from the environment or the command line
+// names a setting and has its type, so it can't spoil the whole config.
+pub fn check_override(path: &str, value: Value) -> Result<Value, String> {
+    let known = serde_json::to_value(Config::default()).expect("serialize default config");
+
+    match lookup(&known, path) {
+        Some(Value::Object(_)) => return Err(format!("'{}' is a table, not a value", path)),
+        Some(_) => {}
+        None => return Err(format!("'{}' doesn't match any config key", path)),
+    }
+
+    serde_json::from_value::<Config>(nest(path, value.clone()))
+        .map(|_| value)
+        .map_err(|e| format!("invalid value for '{}': {}", path, e))
+}
+
+// Values given as text are read as TOML values, so numbers, booleans and
+// arrays keep their type, and anything else is taken as a plain string.
+pub fn parse_value(raw: &str) -> Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .and_then(|value| serde_json::to_value(value).ok())
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+fn nest(path: &str, value: Value) -> Value {
+    path.rsplit('.').fold(value, |value, key| {
+        let mut map = Map::new();
+        map.insert(key.to_string(), value);
+        Value::Object(map)
+    })
+}
+
+pub fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |value, key| value.get(key))
+}
+
+fn merge_value(
+    base: &mut Value,
+    layer: Value,
+    path: &str,
+    source: &Source,
+    origins: &mut BTreeMap<String, Source>,
+) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                let path = if path.is_empty() {
+                    key.to_owned()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+
+                let base = base.entry(key).or_insert(Value::Null);
+                merge_value(base, value, &path, source, origins)
+            }
+        }
+        (base, Value::Object(layer)) => {
+            *base = Value::Object(Map::new());
+            merge_value(base, Value::Object(layer), path, source, origins)
+        }
+        (base, layer) => {
+            *base = layer;
+            origins.insert(path.to_string(), source.clone());
+        }
+    }
+}
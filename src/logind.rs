@@ -0,0 +1,123 @@
+use clap::ValueEnum;
+use std::{sync::mpsc::Sender, thread};
+use zbus::{
+    blocking::{Connection, Proxy, ProxyBuilder},
+    zvariant::{OwnedFd, OwnedValue},
+    CacheProperties,
+};
+
+use crate::sleep::SleepEvent;
+
+// The bus system services are reached on, the session one only makes sense
+// to test against a mock.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Bus {
+    System,
+    Session,
+}
+
+impl Bus {
+    pub fn connect(self) -> zbus::Result<Connection> {
+        match self {
+            Bus::System => Connection::system(),
+            Bus::Session => Connection::session(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Logind {
+    connection: Connection,
+}
+
+impl Logind {
+    pub fn connect(bus: Bus) -> zbus::Result<Self> {
+        Ok(Self {
+            connection: bus.connect()?,
+        })
+    }
+
+    fn get_manager(&self) -> zbus::Result<Proxy<'static>> {
+        Proxy::new(
+            &self.connection,
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+        )
+    }
+
+    // Takes an inhibitor lock, held until the descriptor is closed.
+    pub fn inhibit(&self, what: &str, who: &str, why: &str, mode: &str) -> zbus::Result<OwnedFd> {
+        self.get_manager()?.call("Inhibit", &(what, who, why, mode))
+    }
+
+    // Read from logind every time, not all of them are announced when they change.
+    fn get_manager_property<T>(&self, name: &str) -> zbus::Result<T>
+    where
+        T: TryFrom<OwnedValue>,
+        T::Error: Into<zbus::Error>,
+    {
+        ProxyBuilder::<Proxy>::new_bare(&self.connection)
+            .destination("org.freedesktop.login1")?
+            .path("/org/freedesktop/login1")?
+            .interface("org.freedesktop.login1.Manager")?
+            .cache_properties(CacheProperties::No)
+            .build()?
+            .get_property(name)
+    }
+
+    // What logind does when the lid closes, such as `suspend` or `ignore`.
+    pub fn get_lid_switch_action(&self) -> zbus::Result<String> {
+        self.get_manager_property("HandleLidSwitch")
+    }
+
+    pub fn is_lid_closed(&self) -> zbus::Result<bool> {
+        self.get_manager_property("LidClosed")
+    }
+
+    pub fn is_docked(&self) -> zbus::Result<bool> {
+        self.get_manager_property("Docked")
+    }
+
+    pub fn hibernate(&self) -> zbus::Result<()> {
+        self.get_manager()?.call("Hibernate", &(false,))
+    }
+
+    pub fn suspend(&self) -> zbus::Result<()> {
+        self.get_manager()?.call("Suspend", &(false,))
+    }
+
+    // Goes through the caller's session, which logind lets change the
+    // brightness without the write access the sysfs attribute needs.
+    pub fn set_brightness(&self, subsystem: &str, name: &str, brightness: u32) -> zbus::Result<()> {
+        Proxy::new(
+            &self.connection,
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1/session/auto",
+            "org.freedesktop.login1.Session",
+        )?
+        .call("SetBrightness", &(subsystem, name, brightness))
+    }
+
+    // Forwards `PrepareForSleep` to `sender` from a thread, which lives as
+    // long as the connection.
+    pub fn watch_sleep(&self, sender: Sender<SleepEvent>) -> zbus::Result<()> {
+        let signals = self.get_manager()?.receive_signal("PrepareForSleep")?;
+
+        thread::spawn(move || {
+            for message in signals {
+                let event = match message.body::<bool>() {
+                    Ok(true) => SleepEvent::Suspending,
+                    Ok(false) => SleepEvent::Resumed,
+                    Err(_) => continue,
+                };
+
+                if sender.send(event).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
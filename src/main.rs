@@ -1,64 +1,393 @@
 use clap::Parser;
 use log::{debug, error, info, warn, LevelFilter};
-use notify_rust::NotificationHandle;
 use std::{
-    thread,
-    time::{self, Instant},
+    mem,
+    path::{Path, PathBuf},
+    process, thread, time,
 };
 
+mod agent;
+use agent::*;
+
+mod backlight;
+use backlight::*;
+
+mod broadcast;
+use broadcast::*;
+
+mod bus_service;
+use bus_service::*;
+
 mod cli;
 
+mod capture;
+use capture::*;
+
+mod charge_control;
+use charge_control::*;
+
+mod charging;
+use charging::*;
+
+mod check;
+use check::*;
+
+mod clock;
+use clock::*;
+
 mod config;
 use config::*;
 
+mod control;
+use control::*;
+
+mod dry_run;
+use dry_run::*;
+
+mod event;
+use event::*;
+
+mod hooks;
+use hooks::*;
+
+mod inhibit;
+use inhibit::*;
+
+mod logind;
+use logind::Logind;
+
+mod layers;
+use layers::*;
+
 mod notify;
 use notify::*;
 
+mod peripheral;
+use peripheral::*;
+
+mod polling;
+use polling::*;
+
+mod power_profiles;
+use power_profiles::*;
+
+mod profile;
+use profile::*;
+
+mod schema;
+use schema::*;
+
+mod simulation;
+use simulation::DebugSettings;
+
+mod sleep;
+use sleep::*;
+
+mod sink;
+use sink::*;
+
 mod battery;
 use battery::*;
 
+mod xdg;
+use xdg::*;
+
 fn main() {
+    let mut args = cli::Args::parse();
+
+    let overrides = match args.get_overrides() {
+        Ok(overrides) => overrides,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            process::exit(2)
+        }
+    };
+
+    // The agent goes through the same config loading as the daemon.
+    let agent_socket = match &args.command {
+        Some(cli::Command::Agent { events_socket }) => Some(
+            events_socket
+                .as_ref()
+                .map_or_else(get_events_socket_path, PathBuf::from),
+        ),
+        _ => None,
+    };
+
+    // Subcommands print their own output, they don't need the daemon logs.
+    if let Some(command) = args
+        .command
+        .take()
+        .filter(|command| !matches!(command, cli::Command::Agent { .. }))
+    {
+        let cp = get_config_file(args.config_file);
+        // Commands working on the user file can't do without it.
+        let require_cp = || {
+            cp.as_ref().map(String::as_str).unwrap_or_else(|error| {
+                eprintln!("error: {}, pass --config-file", error);
+                process::exit(1)
+            })
+        };
+
+        match command {
+            cli::Command::CheckConfig {
+                strict,
+                no_fs_checks,
+            } => process::exit(run_check_config(require_cp(), strict, !no_fs_checks)),
+            cli::Command::InitConfig { force } => process::exit(init_config(require_cp(), force)),
+            cli::Command::Schema => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&get_schema()).expect("serialize schema")
+                );
+                return;
+            }
+            cli::Command::Ctl(cli::CtlCommand::Profile { name }) => {
+                let request = match name {
+                    Some(name) => format!("profile {}", name),
+                    None => "profile".to_string(),
+                };
+                process::exit(run_ctl(&request, time::Duration::from_secs(5), args.system))
+            }
+            cli::Command::Ctl(cli::CtlCommand::ChargeLimit { end, start }) => {
+                let request = match end {
+                    Some(end) => format!(
+                        "charge-limit {} {}",
+                        start.map_or("-".to_string(), |start| start.to_string()),
+                        end
+                    ),
+                    None => "charge-limit".to_string(),
+                };
+                // Leaves time to authenticate through the helper.
+                process::exit(run_ctl(
+                    &request,
+                    time::Duration::from_secs(120),
+                    args.system,
+                ))
+            }
+            cli::Command::Agent { .. } => unreachable!("the agent runs with the daemon"),
+            cli::Command::ChargeControlHelper {
+                battery,
+                start,
+                end,
+            } => process::exit(run_charge_control_helper(
+                &battery,
+                ChargeThresholds { start, end },
+            )),
+            cli::Command::Config(cli::ConfigCommand::Dump { effective }) => {
+                print!(
+                    "{}",
+                    dump_config(
+                        &LayeredConfig::load(cp.as_deref().ok(), overrides),
+                        effective
+                    )
+                );
+                return;
+            }
+        }
+    }
+
     env_logger::builder()
         .filter_level(LevelFilter::Debug)
         .init();
 
-    let args = cli::Args::parse();
     debug!("{:#?}", args);
 
-    let cp = get_config_file(args.config_file);
-    debug!("config file path is {}", cp);
+    for kind in BaseDir::ALL {
+        match get_app_dir(kind) {
+            Ok(dir) => debug!("{} directory is {}", kind, dir.display()),
+            Err(error) => debug!("{}", error),
+        }
+    }
+
+    // Without a HOME, as in system units, only the system-wide layers apply.
+    // The system service doesn't belong to any user, it reads a user file
+    // only when given one.
+    let cp = match get_config_file(args.config_file.clone()) {
+        _ if args.system && args.config_file.is_none() => {
+            info!("running as the system service, only the system-wide config is read");
+            None
+        }
+        Ok(cp) => {
+            debug!("config file path is {}", cp);
+            Some(cp)
+        }
+        Err(error) => {
+            warn!("{}, only the system-wide config is read", error);
+            None
+        }
+    };
 
-    let config = Config::parse_or_default(cp);
+    // The overrides are kept for the D-Bus service to reload the config with.
+    let mut config = LayeredConfig::load(cp.as_deref(), overrides.clone()).config;
     debug!("{:#?}", config);
     config.validate();
 
-    // Calculates the notification level based on the provided battery capacity.
-    let get_notification_level = |capacity: u8| -> BatteryNotificationLevel {
-        match capacity {
-            c if c > config.warn.threshold && c <= config.reminder.threshold => {
-                BatteryNotificationLevel::Reminder
+    let clock = Clock::new(args.speed);
+    if args.speed != Speed::default() {
+        info!("running with a {} clock", args.speed);
+    }
+
+    let dry_run = args.dry_run.then(|| DryRun::new(clock.clone()));
+    if dry_run.is_some() {
+        info!("dry run, notifications, sounds and hooks are printed instead");
+    }
+
+    if let Some(path) = agent_socket {
+        run_agent(&config, &path, dry_run)
+    }
+
+    let broadcaster = args.system.then(|| {
+        let path = get_events_socket_path();
+        match Broadcaster::start(&path) {
+            Ok(broadcaster) => {
+                info!("sending events to the agents on {}", path.display());
+                broadcaster
             }
-            c if c > config.threat.threshold && c <= config.warn.threshold => {
-                BatteryNotificationLevel::Warn
+            Err(error) => {
+                error!("unable to listen on {}: {}", path.display(), error);
+                process::exit(1)
             }
-            c if c <= config.threat.threshold => BatteryNotificationLevel::Threat,
-            _ => BatteryNotificationLevel::NoConflict,
         }
-    };
+    });
 
-    let start_time = Instant::now();
-    let sleep_time = time::Duration::from_millis(config.interval_ms);
+    let mut sleep_time = time::Duration::from_millis(config.interval_ms);
 
     let mut last_notification_level = BatteryNotificationLevel::NoConflict;
-    let mut last_notification_handler: Option<NotificationHandle> = None;
-    let mut psc = PowerSupplyClass::new(args.debug_file);
+    let mut last_status = String::new();
+    let mut notifiers =
+        Notifiers::from_config(&config, dry_run.clone()).with_broadcaster(broadcaster.clone());
+    let mut hooks = Hooks::new(config.hooks.clone(), dry_run.clone());
+    let mut charging_monitor = ChargingMonitor::new(&config.not_charging, clock.clone());
+    let mut peripheral_monitor = PeripheralMonitor::new(&config.peripherals);
+    let mut power_profiles = PowerProfileSwitcher::new(
+        &config.power_profiles,
+        args.power_profiles_bus,
+        dry_run.clone(),
+    );
+
+    let debug_settings = match (args.debug_file, args.replay) {
+        (Some(debug_file), _) => Some(DebugSettings::parse(debug_file)),
+        (None, Some(replay)) => {
+            let samples = load_capture(&replay).unwrap_or_else(|error| {
+                error!("unable to load capture {}: {}", replay, error);
+                process::exit(1)
+            });
+            info!("replaying {} samples from {}", samples.len(), replay);
+
+            Some(DebugSettings::from_capture(samples))
+        }
+        (None, None) => None,
+    };
+
+    let mut recorder = args.record.map(|record| {
+        Recorder::create(&record).unwrap_or_else(|error| {
+            error!("unable to create capture {}: {}", record, error);
+            process::exit(1)
+        })
+    });
+
+    let mut psc = PowerSupplyClass::new(args.power_supply_root, debug_settings, clock.clone());
+
+    let battery = PathBuf::from(psc.get_battery_path());
+    apply_charge_thresholds(&battery, &config.charge_control, dry_run.as_ref());
+
+    let control = match get_socket_path(args.system) {
+        Ok(path) => match Control::start(
+            &path,
+            config.profiles.keys().cloned().collect(),
+            battery.clone(),
+            config.charge_control.helper.clone(),
+        ) {
+            Ok(control) => {
+                info!("listening for ctl requests on {}", path.display());
+                Some(control)
+            }
+            Err(error) => {
+                warn!(
+                    "unable to listen on {}, ctl is unavailable: {}",
+                    path.display(),
+                    error
+                );
+                None
+            }
+        },
+        Err(error) => {
+            warn!("{}, ctl is unavailable", error);
+            None
+        }
+    };
+
+    let mut profiles = ProfileSelector::new(&config, clock.clone());
+    let mut poller = Poller::new(clock.clone());
+    let mut sleep_monitor = SleepMonitor::new(args.logind_bus);
+    let mut backlight = BacklightDimmer::new(
+        &config.backlight,
+        args.backlight_root.as_deref().unwrap_or(BACKLIGHT_BASE),
+        sleep_monitor.get_logind().cloned(),
+        dry_run.clone(),
+    );
+    let mut inhibitor = start_inhibitor(&config.sleep, sleep_monitor.get_logind());
+    let mut lid_hibernator = start_lid_hibernator(&config.sleep, sleep_monitor.get_logind());
+
+    // The system service has no session bus to be found on.
+    let service = if config.dbus_service && !args.system {
+        match BusService::start(cp.clone(), overrides, sleep_monitor.get_waker()) {
+            Ok(service) => {
+                info!("owning {} on the session bus", SERVICE_NAME);
+                Some(service)
+            }
+            Err(error) => {
+                warn!(
+                    "unable to own {} on the session bus: {}",
+                    SERVICE_NAME, error
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Takes the config since the active profile may turn sounds off, as may
+    // muting them over D-Bus. The system service has no audio session, the
+    // agents play them.
+    let play_sound = |config: &Config, sound: Sound| {
+        if !config.sound || service.as_ref().is_some_and(BusService::is_muted) {
+            return;
+        }
+
+        match (&dry_run, &broadcaster) {
+            (Some(dry_run), _) => dry_run.print(Action::Sound { sound }),
+            (None, Some(broadcaster)) => broadcaster.send(&Message::Sound { sound }),
+            (None, None) => send_sound_notification(sound),
+        }
+    };
+
+    let mut resumed = false;
+    let mut last_notified_at = time::Duration::ZERO;
+    // A level notification was held back by a snooze.
+    let mut held_back = false;
 
     loop {
+        if psc.is_simulation_over() {
+            info!("the debug simulation played every state, exiting");
+            return;
+        }
+
+        if let Err(error) = psc.refresh() {
+            warn!(
+                "could not refresh the power supply class, skipping: {}",
+                error
+            );
+            resumed |= wait_after_error(&mut sleep_monitor, &mut inhibitor, &clock, sleep_time);
+            continue;
+        }
+
         let capacity = match psc.get_capacity() {
             Ok(capacity) => capacity,
             Err(error) => {
                 warn!("could not read battery capacity, skipping: {}", error);
-                thread::sleep(sleep_time);
+                resumed |= wait_after_error(&mut sleep_monitor, &mut inhibitor, &clock, sleep_time);
                 continue;
             }
         };
@@ -67,38 +396,200 @@ fn main() {
             Ok(status) => status,
             Err(error) => {
                 warn!("could not read battery status, skipping: {}", error);
-                thread::sleep(sleep_time);
+                resumed |= wait_after_error(&mut sleep_monitor, &mut inhibitor, &clock, sleep_time);
                 continue;
             }
         };
 
         info!("current capacity: {} Status: {}", capacity, status);
 
+        let mut test_requested = false;
+        for request in service
+            .as_ref()
+            .map_or_else(Vec::new, BusService::take_requests)
+        {
+            match request {
+                ServiceRequest::Reload(reloaded) => {
+                    info!("reloading the config");
+                    let previous = mem::replace(&mut config, *reloaded);
+                    debug!("{:#?}", config);
+
+                    sleep_time = time::Duration::from_millis(config.interval_ms);
+                    notifiers = Notifiers::from_config(&config, dry_run.clone())
+                        .with_broadcaster(broadcaster.clone());
+                    hooks = Hooks::new(config.hooks.clone(), dry_run.clone());
+                    charging_monitor.set_config(&config.not_charging);
+                    peripheral_monitor.set_config(&config.peripherals);
+                    power_profiles = PowerProfileSwitcher::new(
+                        &config.power_profiles,
+                        args.power_profiles_bus,
+                        dry_run.clone(),
+                    );
+                    profiles.set_base(&config);
+                    if let Some(control) = &control {
+                        control.set_profiles(config.profiles.keys().cloned().collect());
+                        control.set_helper(config.charge_control.helper.clone());
+                    }
+
+                    backlight.set_config(
+                        &config.backlight,
+                        args.backlight_root.as_deref().unwrap_or(BACKLIGHT_BASE),
+                        sleep_monitor.get_logind().cloned(),
+                    );
+                    // Dropping the previous ones releases their locks.
+                    inhibitor = start_inhibitor(&config.sleep, sleep_monitor.get_logind());
+                    lid_hibernator =
+                        start_lid_hibernator(&config.sleep, sleep_monitor.get_logind());
+
+                    // Thresholds set with ctl since are kept unless the config changes them.
+                    if config.charge_control.get_thresholds()
+                        != previous.charge_control.get_thresholds()
+                    {
+                        apply_charge_thresholds(&battery, &config.charge_control, dry_run.as_ref());
+                    }
+                }
+                ServiceRequest::TestNotification => test_requested = true,
+            }
+        }
+
         // The kernel reports several statuses for a plugged-in adapter ("Charging"
         // while filling, "Full" once topped off, "Not charging" when held below a
         // charge limit), and may sit on "Unknown" for a few seconds while the EC
         // settles after plug-in. Anything that is not "Discharging" or "Unknown"
-        // means the adapter is connected.
-        let plugged_in = matches!(status.as_str(), "Charging" | "Full" | "Not charging");
+        // means the adapter is connected. Adapter nodes know better than that
+        // though, so their `online` attribute wins when the machine exposes them.
+        let online_adapter = psc.get_online_adapter();
+        let plugged_in = match &online_adapter {
+            Some(adapter) => adapter.is_some(),
+            None => matches!(status.as_str(), "Charging" | "Full" | "Not charging"),
+        };
+
+        if let Some(control) = &control {
+            profiles.set_manual(control.get_manual_profile());
+        }
+        profiles.update(plugged_in);
+        if let Some(control) = &control {
+            control.set_active_profile(profiles.get_name());
+        }
+
+        if resumed {
+            info!("the system resumed, the battery is evaluated again");
+            resumed = false;
+
+            // Whatever was notified before the suspension is stale, the level the
+            // battery is in now is notified again.
+            if last_notification_level != BatteryNotificationLevel::Charging {
+                last_notification_level = BatteryNotificationLevel::NoConflict;
+            }
+            charging_monitor.reset();
+        }
+
+        // The level held back is notified once the snooze is over, without
+        // entering it a second time.
+        let snooze_over = held_back && !service.as_ref().is_some_and(BusService::is_snoozed);
+        if snooze_over {
+            info!("the snooze is over, the level held back is notified");
+            held_back = false;
+        }
+
+        // Settings a profile can change are read from here on.
+        let config = profiles.get_config();
+
+        if test_requested {
+            let event = Event {
+                kind: EventKind::Test,
+                level: last_notification_level,
+                urgency: Urgency::NORMAL,
+                capacity,
+                status: status.to_owned(),
+                title: "Test notification".to_string(),
+                content: format!("Charge: {}%.\nNotifications will look like this", capacity),
+                device: None,
+            };
+            notifiers.notify(config.sinks_for(&[]), &event);
+
+            play_sound(config, Sound::Warn);
+        }
+
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.record(&Sample {
+                at_ms: clock.now().as_millis() as u64,
+                capacity,
+                status: status.to_owned(),
+                power_now: psc.get_power_now(),
+                energy_full: psc.get_energy_full(),
+                adapter_online: online_adapter.as_ref().map(|a| a.is_some()),
+            });
+        }
+
+        // A connected adapter too weak for the load still drains the battery, its
+        // levels are notified as if it was unplugged.
+        let draining = status == "Discharging";
 
         // This double check is necessary to don't perform the same action repeated times
-        if plugged_in && last_notification_level != BatteryNotificationLevel::Charging {
+        if plugged_in && !draining && last_notification_level != BatteryNotificationLevel::Charging
+        {
             info!("now the battery is plugged in (status: {})...", status);
             info!(
                 "the last notified capacity will be restarted to 0 (it was {})",
                 last_notification_level
             );
 
-            if start_time.elapsed().as_secs() > 5 {
-                last_notification_handler.take().map(|h| h.close());
-                send_sound_notification(CHARGING_BATTERY_SOUND);
+            last_notification_level = BatteryNotificationLevel::Charging;
+            power_profiles.plug();
+            backlight.plug();
+
+            if clock.now().as_secs() > 5 {
+                notifiers.dismiss();
+                play_sound(config, Sound::Charging);
+                hooks.run(
+                    EventKind::Plug,
+                    capacity,
+                    &status,
+                    last_notification_level,
+                    None,
+                );
             } else {
                 warn!("the app started with the computer plugged in, nothing to do");
             }
+        } else if (!plugged_in && status != "Unknown") || draining {
+            if last_notification_level == BatteryNotificationLevel::Charging && plugged_in {
+                warn!("the battery is discharging while the adapter is connected");
+
+                last_notification_level = BatteryNotificationLevel::NoConflict;
+            } else if last_notification_level == BatteryNotificationLevel::Charging {
+                info!("now the battery is unplugged");
+
+                // Forget the charging state so the level below is notified again.
+                last_notification_level = BatteryNotificationLevel::NoConflict;
+
+                if config.unplug.enabled {
+                    let vars = template_vars(capacity, psc.get_time_remaining());
+                    let event = Event {
+                        kind: EventKind::Unplug,
+                        level: last_notification_level,
+                        urgency: Urgency::LOW,
+                        capacity,
+                        status: status.to_owned(),
+                        title: config.unplug.render_title(&vars),
+                        content: config.unplug.render_content(&vars),
+                        device: None,
+                    };
+                    notifiers.notify(config.sinks_for(&config.unplug.sinks), &event);
+
+                    play_sound(config, Sound::Unplugged);
+                }
+
+                hooks.run(
+                    EventKind::Unplug,
+                    capacity,
+                    &status,
+                    last_notification_level,
+                    None,
+                );
+            }
 
-            last_notification_level = BatteryNotificationLevel::Charging
-        } else if status == "Discharging" {
-            let current_notification_level = get_notification_level(capacity);
+            let current_notification_level = config.get_notification_level(capacity);
 
             if current_notification_level != BatteryNotificationLevel::NoConflict {
                 let (urgency, bound) = match current_notification_level {
@@ -113,27 +604,44 @@ fn main() {
                     last_notification_level, current_notification_level
                 );
 
-                if last_notification_level != current_notification_level {
+                let repeat_due = config.repeat_s > 0
+                    && clock.now() - last_notified_at >= time::Duration::from_secs(config.repeat_s);
+
+                let entered = last_notification_level != current_notification_level;
+                if entered || repeat_due || snooze_over {
                     last_notification_level = current_notification_level;
-                    last_notification_handler.take().map(|h| h.close());
+                    last_notified_at = clock.now();
+                    let kind = EventKind::from_level(current_notification_level)
+                        .expect("notification level maps to an event");
 
-                    let result = send_desktop_notification(
+                    let vars = template_vars(capacity, psc.get_time_remaining());
+                    let event = Event {
+                        kind,
+                        level: current_notification_level,
                         urgency,
-                        bound.render_title(capacity).as_str(),
-                        bound.render_content(capacity).as_str(),
-                        config.icon_path.to_owned(),
-                    );
-
-                    if result.is_ok() {
-                        last_notification_handler = Some(result.unwrap());
+                        capacity,
+                        status: status.to_owned(),
+                        title: bound.render_title(&vars),
+                        content: bound.render_content(&vars),
+                        device: None,
+                    };
+                    if service.as_ref().is_some_and(BusService::is_snoozed) {
+                        info!(
+                            "notifications are snoozed, holding the {} back",
+                            kind.name()
+                        );
+                        held_back = true;
                     } else {
-                        error!(
-                            "error sending desktop notification: {}",
-                            result.unwrap_err()
-                        )
+                        notifiers.notify(config.sinks_for(&bound.sinks), &event);
+
+                        play_sound(config, urgency.get_sound());
+                        hooks.run(kind, capacity, &status, current_notification_level, None);
                     }
 
-                    send_sound_notification(urgency.get_sound());
+                    if entered {
+                        power_profiles.enter_level(current_notification_level);
+                        backlight.enter_level(current_notification_level);
+                    }
                 };
 
                 info!(
@@ -143,6 +651,292 @@ fn main() {
             }
         }
 
-        thread::sleep(sleep_time);
+        let adapter = online_adapter.flatten();
+        let fault = charging_monitor.update(
+            plugged_in,
+            &status,
+            capacity,
+            psc.get_charge_thresholds(),
+            adapter.as_ref(),
+        );
+        if let Some(fault) = fault {
+            warn!("the charger is connected but {}", fault);
+
+            if config.not_charging.enabled {
+                let mut vars = template_vars(capacity, psc.get_time_remaining());
+                vars.push(("reason", fault.to_string()));
+                vars.push((
+                    "adapter",
+                    adapter
+                        .as_ref()
+                        .map_or("unknown".to_string(), |a| a.describe()),
+                ));
+                vars.push((
+                    "adapter_watts",
+                    adapter
+                        .as_ref()
+                        .and_then(|a| a.max_watts)
+                        .map_or("unknown".to_string(), |w| format!("{:.0}", w)),
+                ));
+
+                let event = Event {
+                    kind: EventKind::NotCharging,
+                    level: last_notification_level,
+                    urgency: Urgency::NORMAL,
+                    capacity,
+                    status: status.to_owned(),
+                    title: config.not_charging.render_title(&vars),
+                    content: config.not_charging.render_content(&vars),
+                    device: None,
+                };
+                notifiers.notify(config.sinks_for(&config.not_charging.sinks), &event);
+
+                play_sound(config, Sound::Warn);
+            }
+
+            hooks.run(
+                EventKind::NotCharging,
+                capacity,
+                &status,
+                last_notification_level,
+                None,
+            );
+        }
+
+        if status == "Full" && !last_status.is_empty() && last_status != "Full" {
+            info!("the battery is fully charged");
+            hooks.run(
+                EventKind::Full,
+                capacity,
+                &status,
+                last_notification_level,
+                None,
+            );
+        }
+
+        last_status = status;
+
+        if config.peripherals.enabled {
+            for (peripheral, level) in peripheral_monitor.update(psc.get_peripherals()) {
+                info!(
+                    "peripheral {} ({}) entered level {}",
+                    peripheral.model_name, peripheral.name, level
+                );
+
+                let (urgency, bound) = match level {
+                    BatteryNotificationLevel::Warn => (Urgency::LOW, &config.peripherals.warn),
+                    _ => (Urgency::NORMAL, &config.peripherals.threat),
+                };
+
+                let mut vars = template_vars(peripheral.capacity, None);
+                vars.push(("model_name", peripheral.model_name.to_owned()));
+                vars.push(("device", peripheral.name.to_owned()));
+
+                let event = Event {
+                    kind: EventKind::Peripheral,
+                    level,
+                    urgency,
+                    capacity: peripheral.capacity,
+                    status: peripheral.status.to_owned(),
+                    title: bound.render_title(&vars),
+                    content: bound.render_content(&vars),
+                    device: Some(peripheral.name.to_owned()),
+                };
+                notifiers.notify(config.sinks_for(&bound.sinks), &event);
+
+                play_sound(config, urgency.get_sound());
+                hooks.run(
+                    EventKind::Peripheral,
+                    peripheral.capacity,
+                    &peripheral.status,
+                    level,
+                    Some(&peripheral.name),
+                );
+            }
+        }
+
+        if let Some(lid_hibernator) = lid_hibernator.as_mut() {
+            if draining && capacity <= config.threat.threshold {
+                lid_hibernator.arm();
+            } else {
+                lid_hibernator.disarm();
+            }
+        }
+
+        if let Some(service) = &service {
+            service.publish(
+                capacity,
+                &last_status,
+                last_notification_level,
+                psc.get_time_remaining(),
+            );
+        }
+
+        // A weak adapter doesn't stop the drain, the levels are still entered.
+        let interval = poller.next_interval(
+            config,
+            capacity,
+            plugged_in && !draining,
+            psc.get_time_remaining(),
+        );
+        if config.polling.adaptive {
+            debug!("next check in {:?}", interval);
+        }
+
+        // Nothing is checked again until the system resumes once it's
+        // about to suspend, logind tells about a cancelled suspend as a resume.
+        let mut suspending = false;
+        loop {
+            match sleep_monitor.sleep(&clock, interval) {
+                Some(SleepEvent::Suspending) => {
+                    info!("the system is about to suspend");
+                    suspending = true;
+
+                    let Some(inhibitor) = inhibitor.as_mut().filter(|i| i.is_held()) else {
+                        continue;
+                    };
+
+                    let battery = psc
+                        .refresh()
+                        .ok()
+                        .and_then(|_| Some((psc.get_capacity().ok()?, psc.get_status().ok()?)));
+                    let critical = battery.filter(|(capacity, status)| {
+                        status == "Discharging" && *capacity <= config.threat.threshold
+                    });
+
+                    if let Some((capacity, status)) = critical {
+                        warn!("suspending with a critical battery ({}%)", capacity);
+
+                        let warning = &config.sleep.warning;
+                        if warning.enabled {
+                            let vars = template_vars(capacity, psc.get_time_remaining());
+                            let event = Event {
+                                kind: EventKind::Suspend,
+                                level: last_notification_level,
+                                urgency: Urgency::CRITICAL,
+                                capacity,
+                                status: status.to_owned(),
+                                title: warning.render_title(&vars),
+                                content: warning.render_content(&vars),
+                                device: None,
+                            };
+                            notifiers.notify(config.sinks_for(&warning.sinks), &event);
+
+                            play_sound(config, Sound::Threat);
+                        }
+
+                        hooks.run(
+                            EventKind::Suspend,
+                            capacity,
+                            &status,
+                            last_notification_level,
+                            None,
+                        );
+
+                        // Leaves the warning on screen for a moment.
+                        clock.sleep(time::Duration::from_millis(config.sleep.warn_ms));
+                    }
+
+                    inhibitor.release();
+                }
+                Some(SleepEvent::Resumed) => {
+                    if let Some(inhibitor) = inhibitor.as_mut() {
+                        inhibitor.acquire();
+                    }
+                    resumed = true;
+                    break;
+                }
+                Some(SleepEvent::Wake) | None if suspending => {}
+                Some(SleepEvent::Wake) | None => break,
+            }
+        }
+    }
+}
+
+// Writes the thresholds of the config unless the battery already has them.
+fn apply_charge_thresholds(battery: &Path, config: &ChargeControlConfig, dry_run: Option<&DryRun>) {
+    let thresholds = config.get_thresholds();
+    if thresholds.is_applied(&ChargeThresholds::read(battery)) {
+        return;
+    }
+
+    match dry_run {
+        Some(dry_run) => dry_run.print(Action::ChargeThresholds {
+            start: thresholds.start,
+            end: thresholds.end,
+        }),
+        // The helper may wait for someone to authenticate, the battery is
+        // watched in the meantime.
+        None => {
+            let battery = battery.to_owned();
+            let helper = config.helper.clone();
+            thread::spawn(
+                move || match set_thresholds(&battery, thresholds, &helper) {
+                    Ok(()) => info!("charge thresholds set, {}", thresholds),
+                    Err(error) => warn!("unable to set the charge thresholds: {}", error),
+                },
+            );
+        }
+    }
+}
+
+// Holds suspends back when `sleep.inhibit` asks for it.
+fn start_inhibitor(config: &SleepConfig, logind: Option<&Logind>) -> Option<SleepInhibitor> {
+    match (config.inhibit, logind) {
+        (true, Some(logind)) => {
+            let mut inhibitor = SleepInhibitor::new(logind.clone());
+            inhibitor.acquire();
+            Some(inhibitor)
+        }
+        (true, None) => {
+            warn!("logind is unreachable, suspends can't be held back");
+            None
+        }
+        (false, _) => None,
+    }
+}
+
+// Watches the lid at the threat level when `sleep.critical_action` hibernates.
+fn start_lid_hibernator(config: &SleepConfig, logind: Option<&Logind>) -> Option<LidHibernator> {
+    match (config.critical_action, logind) {
+        (CriticalAction::Hibernate, Some(logind)) => Some(LidHibernator::new(logind.clone())),
+        (CriticalAction::Hibernate, None) => {
+            warn!("logind is unreachable, closing the lid won't hibernate");
+            None
+        }
+        (CriticalAction::Warn, _) => None,
+    }
+}
+
+// Waits `duration` after a check failed. Without a battery reading there is
+// nothing to warn about before a suspend, the delay lock is released right
+// away. Returns whether the system resumed.
+fn wait_after_error(
+    sleep_monitor: &mut SleepMonitor,
+    inhibitor: &mut Option<SleepInhibitor>,
+    clock: &Clock,
+    duration: time::Duration,
+) -> bool {
+    let mut suspending = false;
+
+    loop {
+        match sleep_monitor.sleep(clock, duration) {
+            Some(SleepEvent::Suspending) => {
+                info!("the system is about to suspend");
+                suspending = true;
+
+                if let Some(inhibitor) = inhibitor.as_mut() {
+                    inhibitor.release();
+                }
+            }
+            Some(SleepEvent::Resumed) => {
+                if let Some(inhibitor) = inhibitor.as_mut() {
+                    inhibitor.acquire();
+                }
+                return true;
+            }
+            Some(SleepEvent::Wake) | None if suspending => {}
+            Some(SleepEvent::Wake) | None => return false,
+        }
     }
 }
@@ -1,5 +1,6 @@
 use log::{debug, error};
 use notify_rust::{error, Hint, Notification, NotificationHandle};
+use serde::{Deserialize, Serialize};
 use soloud::{audio::Wav, AudioExt, LoadExt, Soloud};
 use std::{env, fmt, path::Path, thread, time};
 
@@ -9,20 +10,45 @@ pub const CHARGING_BATTERY_SOUND: &[u8] = include_bytes!("./../assets/sounds/cha
 pub const REMINDER_BATTERY_SOUND: &[u8] = include_bytes!("./../assets/sounds/30.mp3");
 pub const THREAT_BATTERY_SOUND: &[u8] = include_bytes!("./../assets/sounds/5.mp3");
 pub const WARN_BATTERY_SOUND: &[u8] = include_bytes!("./../assets/sounds/15.mp3");
+pub const UNPLUGGED_BATTERY_SOUND: &[u8] = include_bytes!("./../assets/sounds/unplugged.wav");
 
-#[derive(Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Urgency {
     CRITICAL,
     NORMAL,
     LOW,
 }
 
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sound {
+    Charging,
+    Unplugged,
+    Reminder,
+    Warn,
+    Threat,
+}
+
+impl Sound {
+    pub fn get_bytes(&self) -> &'static [u8] {
+        match self {
+            Sound::Charging => CHARGING_BATTERY_SOUND,
+            Sound::Unplugged => UNPLUGGED_BATTERY_SOUND,
+            Sound::Reminder => REMINDER_BATTERY_SOUND,
+            Sound::Warn => WARN_BATTERY_SOUND,
+            Sound::Threat => THREAT_BATTERY_SOUND,
+        }
+    }
+}
+
 impl Urgency {
-    pub fn get_sound(&self) -> &'static [u8] {
+    pub fn get_sound(&self) -> Sound {
         match self {
-            Urgency::CRITICAL => THREAT_BATTERY_SOUND,
-            Urgency::NORMAL => WARN_BATTERY_SOUND,
-            Urgency::LOW => REMINDER_BATTERY_SOUND,
+            Urgency::CRITICAL => Sound::Threat,
+            Urgency::NORMAL => Sound::Warn,
+            Urgency::LOW => Sound::Reminder,
         }
     }
 
@@ -51,18 +77,16 @@ pub fn send_desktop_notification(
     content: &str,
     icon_path: Option<String>,
 ) -> error::Result<NotificationHandle> {
-    let result = Notification::new()
+    Notification::new()
         .summary(title)
         .body(content)
         .icon(&get_icon_path_or_default(icon_path))
         .hint(Hint::Category("string:x-stack-tag:battery".to_string()))
         .hint(Hint::Urgency(urgency.get_for_third_party()))
-        .show();
-
-    result.map(|r| return r)
+        .show()
 }
 
-pub fn send_sound_notification(sound: &'static [u8]) {
+pub fn send_sound_notification(sound: Sound) {
     // Playback runs on its own thread so opening the audio device and waiting for
     // the sound to finish never blocks the battery polling loop.
     thread::spawn(move || {
@@ -72,7 +96,7 @@ pub fn send_sound_notification(sound: &'static [u8]) {
             Ok(sl) => {
                 let mut wav = Wav::default();
 
-                match wav.load_mem(sound) {
+                match wav.load_mem(sound.get_bytes()) {
                     Ok(r) => debug!("sound file has been loaded: {:#?}", r),
                     Err(error) => {
                         error!("couldn't load sound file: {}", error.to_string());
@@ -94,9 +118,8 @@ pub fn send_sound_notification(sound: &'static [u8]) {
 }
 
 pub fn get_icon_path_or_default(icon_path: Option<String>) -> String {
-    if icon_path.is_some() {
-        let p = icon_path.unwrap();
-        if p != "" {
+    if let Some(p) = icon_path {
+        if !p.is_empty() {
             return p;
         }
     };
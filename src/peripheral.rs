@@ -0,0 +1,80 @@
+use log::info;
+use std::collections::HashMap;
+
+use crate::{
+    battery::{BatteryNotificationLevel, Peripheral},
+    config::PeripheralsConfig,
+};
+
+// Remembers the last notified level of every peripheral, keyed by its power
+// supply node, so each device is only notified once per level.
+pub struct PeripheralMonitor {
+    warn_threshold: u8,
+    threat_threshold: u8,
+    last_levels: HashMap<String, BatteryNotificationLevel>,
+}
+
+impl PeripheralMonitor {
+    pub fn new(config: &PeripheralsConfig) -> Self {
+        Self {
+            warn_threshold: config.warn.threshold,
+            threat_threshold: config.threat.threshold,
+            last_levels: HashMap::new(),
+        }
+    }
+
+    // Levels already notified are kept across a reload.
+    pub fn set_config(&mut self, config: &PeripheralsConfig) {
+        self.warn_threshold = config.warn.threshold;
+        self.threat_threshold = config.threat.threshold;
+    }
+
+    fn get_level(&self, peripheral: &Peripheral) -> BatteryNotificationLevel {
+        if matches!(peripheral.status.as_str(), "Charging" | "Full") {
+            return BatteryNotificationLevel::Charging;
+        }
+
+        match peripheral.capacity {
+            c if c <= self.threat_threshold => BatteryNotificationLevel::Threat,
+            c if c <= self.warn_threshold => BatteryNotificationLevel::Warn,
+            _ => BatteryNotificationLevel::NoConflict,
+        }
+    }
+
+    // Returns the peripherals that just entered the warn or threat level.
+    pub fn update(
+        &mut self,
+        peripherals: Vec<Peripheral>,
+    ) -> Vec<(Peripheral, BatteryNotificationLevel)> {
+        let connected: Vec<String> = peripherals.iter().map(|p| p.name.to_owned()).collect();
+        self.last_levels.retain(|name, _| {
+            let keep = connected.contains(name);
+            if !keep {
+                info!("peripheral {} is gone", name);
+            }
+
+            keep
+        });
+
+        let mut entered = Vec::new();
+
+        for peripheral in peripherals {
+            let level = self.get_level(&peripheral);
+            let last_level = self
+                .last_levels
+                .insert(peripheral.name.to_owned(), level)
+                .unwrap_or(BatteryNotificationLevel::NoConflict);
+
+            if level != last_level
+                && matches!(
+                    level,
+                    BatteryNotificationLevel::Warn | BatteryNotificationLevel::Threat
+                )
+            {
+                entered.push((peripheral, level));
+            }
+        }
+
+        entered
+    }
+}
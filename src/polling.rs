@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use crate::{clock::Clock, config::Config};
+
+// Drain assumed until one is known, as fast as a laptop under load goes.
+const ASSUMED_DRAIN_PER_MINUTE: f64 = 1.0;
+
+// Picks the time until the next battery check. In adaptive mode the check
+// happens at least twice before the next threshold could be crossed at the
+// current drain, so high capacities or a plugged in adapter mean few wakeups
+// while low capacities keep the reaction fast.
+pub struct Poller {
+    clock: Clock,
+    // First reading since the laptop runs on battery, to observe the drain
+    // when the kernel doesn't report it.
+    discharge_start: Option<(Duration, u8)>,
+}
+
+impl Poller {
+    pub fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            discharge_start: None,
+        }
+    }
+
+    pub fn next_interval(
+        &mut self,
+        config: &Config,
+        capacity: u8,
+        plugged_in: bool,
+        time_remaining: Option<Duration>,
+    ) -> Duration {
+        let polling = &config.polling;
+        if !polling.adaptive {
+            return Duration::from_millis(config.interval_ms);
+        }
+
+        let min = Duration::from_millis(polling.min_interval_ms);
+        let max = Duration::from_millis(polling.max_interval_ms);
+
+        if plugged_in {
+            self.discharge_start = None;
+            return max;
+        }
+
+        let now = self.clock.now();
+        let (started_at, start_capacity) = *self.discharge_start.get_or_insert((now, capacity));
+
+        // The next level is entered once the capacity reaches its threshold.
+        let next_threshold = [
+            config.reminder.threshold,
+            config.warn.threshold,
+            config.threat.threshold,
+        ]
+        .into_iter()
+        .filter(|threshold| *threshold < capacity)
+        .max();
+
+        let Some(next_threshold) = next_threshold else {
+            return min;
+        };
+
+        // Percent per second, the kernel estimate first since it follows the
+        // load, then the drop observed since the adapter was unplugged.
+        let reported = time_remaining
+            .filter(|t| !t.is_zero())
+            .map(|t| capacity as f64 / t.as_secs_f64());
+        let observed = (start_capacity > capacity && now > started_at)
+            .then(|| (start_capacity - capacity) as f64 / (now - started_at).as_secs_f64());
+        let drain = reported
+            .or(observed)
+            .unwrap_or(ASSUMED_DRAIN_PER_MINUTE / 60.0);
+
+        if drain <= 0.0 {
+            return max;
+        }
+
+        let until_threshold = (capacity - next_threshold) as f64 / drain;
+        Duration::try_from_secs_f64(until_threshold / 2.0).map_or(max, |d| d.clamp(min, max))
+    }
+}
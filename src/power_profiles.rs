@@ -0,0 +1,219 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::PathBuf};
+use zbus::blocking::{Connection, Proxy};
+
+use crate::{
+    battery::BatteryNotificationLevel,
+    config::PowerProfilesConfig,
+    dry_run::{Action, DryRun},
+    logind::Bus,
+    xdg::{get_app_dir, BaseDir},
+};
+
+pub const POWER_PROFILES: [&str; 3] = ["power-saver", "balanced", "performance"];
+
+struct PowerProfilesDaemon {
+    connection: Connection,
+}
+
+impl PowerProfilesDaemon {
+    fn get_proxy(&self) -> zbus::Result<Proxy<'static>> {
+        Proxy::new(
+            &self.connection,
+            "net.hadess.PowerProfiles",
+            "/net/hadess/PowerProfiles",
+            "net.hadess.PowerProfiles",
+        )
+    }
+
+    fn get_active_profile(&self) -> zbus::Result<String> {
+        self.get_proxy()?.get_property("ActiveProfile")
+    }
+
+    fn set_active_profile(&self, profile: &str) -> zbus::Result<()> {
+        self.get_proxy()?
+            .set_property("ActiveProfile", profile)
+            .map_err(zbus::Error::from)
+    }
+}
+
+// What is restored on plug-in, kept in the state directory so restarting the
+// daemon on battery doesn't lose it.
+#[derive(Debug, Deserialize, Serialize)]
+struct SwitchState {
+    // Active before the first switch.
+    previous: String,
+    // Last one set by the daemon, a profile picked by hand since then is left alone.
+    applied: String,
+}
+
+fn get_state_path() -> Option<PathBuf> {
+    match get_app_dir(BaseDir::State) {
+        Ok(dir) => Some(dir.join("power-profile.json")),
+        Err(error) => {
+            warn!(
+                "{}, the power profile to restore is only kept in memory",
+                error
+            );
+            None
+        }
+    }
+}
+
+// Switches power profiles as the battery drains and restores the previous
+// one once the adapter is plugged in.
+pub struct PowerProfileSwitcher {
+    config: PowerProfilesConfig,
+    daemon: Option<PowerProfilesDaemon>,
+    dry_run: Option<DryRun>,
+    state_path: Option<PathBuf>,
+    state: Option<SwitchState>,
+}
+
+impl PowerProfileSwitcher {
+    pub fn new(config: &PowerProfilesConfig, bus: Bus, dry_run: Option<DryRun>) -> Self {
+        let mut switcher = Self {
+            config: config.clone(),
+            daemon: None,
+            dry_run,
+            state_path: None,
+            state: None,
+        };
+
+        if !config.enabled {
+            return switcher;
+        }
+
+        match bus.connect() {
+            Ok(connection) => switcher.daemon = Some(PowerProfilesDaemon { connection }),
+            Err(error) => warn!(
+                "unable to reach power-profiles-daemon, profiles won't be switched: {}",
+                error
+            ),
+        }
+
+        // A dry run leaves the state of the real daemon alone.
+        if switcher.dry_run.is_none() {
+            switcher.state_path = get_state_path();
+            switcher.state = switcher.state_path.as_ref().and_then(|path| {
+                let content = fs::read_to_string(path).ok()?;
+                serde_json::from_str(&content)
+                    .map_err(|error| warn!("ignoring {}: {}", path.display(), error))
+                    .ok()
+            });
+        }
+
+        switcher
+    }
+
+    fn save_state(&self) {
+        let Some(path) = &self.state_path else {
+            return;
+        };
+
+        let result = match &self.state {
+            Some(state) => path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| {
+                    let content = serde_json::to_string(state).expect("serialize switch state");
+                    fs::write(path, content)
+                }),
+            None => fs::remove_file(path).or_else(|error| match error.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(error),
+            }),
+        };
+
+        if let Err(error) = result {
+            warn!("unable to update {}: {}", path.display(), error);
+        }
+    }
+
+    fn set_profile(&self, daemon: &PowerProfilesDaemon, profile: &str) -> bool {
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.print(Action::PowerProfile { profile });
+            return true;
+        }
+
+        match daemon.set_active_profile(profile) {
+            Ok(()) => {
+                info!("switched to the '{}' power profile", profile);
+                true
+            }
+            Err(error) => {
+                warn!(
+                    "unable to switch to the '{}' power profile: {}",
+                    profile, error
+                );
+                false
+            }
+        }
+    }
+
+    pub fn enter_level(&mut self, level: BatteryNotificationLevel) {
+        let Some(daemon) = &self.daemon else {
+            return;
+        };
+
+        let profile = self.config.get_profile(level);
+        if profile.is_empty() {
+            return;
+        }
+
+        let active = match daemon.get_active_profile() {
+            Ok(active) => active,
+            Err(error) => {
+                warn!("unable to read the active power profile: {}", error);
+                return;
+            }
+        };
+
+        if active == profile || !self.set_profile(daemon, profile) {
+            return;
+        }
+
+        let previous = match self.state.take() {
+            Some(state) => state.previous,
+            None => active,
+        };
+        self.state = Some(SwitchState {
+            previous,
+            applied: profile.to_string(),
+        });
+        self.save_state();
+    }
+
+    pub fn plug(&mut self) {
+        let Some(daemon) = &self.daemon else {
+            return;
+        };
+
+        if !self.config.restore_on_plug {
+            return;
+        }
+
+        let Some(state) = self.state.take() else {
+            return;
+        };
+
+        match daemon.get_active_profile() {
+            Ok(active) if active == state.applied => {
+                self.set_profile(daemon, &state.previous);
+            }
+            Ok(active) => info!(
+                "the '{}' power profile was picked since the last switch, keeping it",
+                active
+            ),
+            // Tried again on the next plug-in.
+            Err(error) => {
+                warn!("unable to read the active power profile: {}", error);
+                self.state = Some(state);
+                return;
+            }
+        }
+
+        self.save_state();
+    }
+}
@@ -0,0 +1,168 @@
+use log::info;
+use std::{fs, mem, ptr, str::FromStr, time::Duration};
+
+use crate::{
+    clock::Clock,
+    config::{Config, ProfileRule},
+};
+
+// A window of the day in minutes since midnight, the end excluded. Windows
+// ending before they start wrap around midnight, equal bounds span the day.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeWindow {
+    start: u32,
+    end: u32,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            self.start == self.end || (self.start <= minute && minute < self.end)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_time = |time: &str| -> Option<u32> {
+            let (hours, minutes) = time.trim().split_once(':')?;
+            let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+
+            (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+        };
+
+        s.split_once('-')
+            .and_then(|(start, end)| Some((parse_time(start)?, parse_time(end)?)))
+            .map(|(start, end)| TimeWindow { start, end })
+            .ok_or_else(|| format!("invalid time window '{}', expected HH:MM-HH:MM", s))
+    }
+}
+
+// Minutes since midnight in the local timezone.
+fn get_local_minute() -> u32 {
+    unsafe {
+        let now = libc::time(ptr::null_mut());
+        let mut tm: libc::tm = mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+
+        (tm.tm_hour * 60 + tm.tm_min) as u32
+    }
+}
+
+fn is_process_running(name: &str) -> bool {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return false;
+    };
+
+    entries.flatten().any(|entry| {
+        fs::read_to_string(entry.path().join("comm")).is_ok_and(|comm| comm.trim_end() == name)
+    })
+}
+
+// Picks the active profile from the rules, unless one was selected by hand,
+// and keeps the config it results in.
+pub struct ProfileSelector {
+    base: Config,
+    active: Config,
+    name: Option<String>,
+    manual: Option<String>,
+    clock: Clock,
+    // Adapter presence, and when it last changed on the shared clock. The
+    // history starts with the daemon, nothing is known from before.
+    plugged_in: Option<bool>,
+    changed_at: Duration,
+}
+
+impl ProfileSelector {
+    pub fn new(config: &Config, clock: Clock) -> Self {
+        Self {
+            base: config.clone(),
+            active: config.clone(),
+            name: None,
+            manual: None,
+            changed_at: clock.now(),
+            clock,
+            plugged_in: None,
+        }
+    }
+
+    pub fn get_config(&self) -> &Config {
+        &self.active
+    }
+
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    // Takes a reloaded config, the profile is selected again on the next update.
+    pub fn set_base(&mut self, config: &Config) {
+        self.base = config.clone();
+        self.active = config.clone();
+        self.name = None;
+    }
+
+    // A profile selected by hand wins over the rules until it's unset.
+    pub fn set_manual(&mut self, name: Option<String>) {
+        self.manual = name.filter(|name| self.base.profiles.contains_key(name));
+    }
+
+    pub fn update(&mut self, plugged_in: bool) {
+        let now = self.clock.now();
+        if self.plugged_in != Some(plugged_in) {
+            self.plugged_in = Some(plugged_in);
+            self.changed_at = now;
+        }
+
+        let selected = self.manual.clone().or_else(|| {
+            self.base
+                .profile_rules
+                .iter()
+                .find(|rule| self.matches(rule, now))
+                .map(|rule| rule.profile.to_owned())
+        });
+
+        if selected == self.name {
+            return;
+        }
+
+        match &selected {
+            Some(name) => info!("switching to the '{}' profile", name),
+            None => info!(
+                "leaving the '{}' profile",
+                self.name.as_deref().unwrap_or("")
+            ),
+        }
+
+        self.active = match selected
+            .as_ref()
+            .and_then(|name| self.base.profiles.get(name))
+        {
+            Some(profile) => self.base.with_profile(profile),
+            None => self.base.clone(),
+        };
+        self.name = selected;
+    }
+
+    fn matches(&self, rule: &ProfileRule, now: Duration) -> bool {
+        let plugged_in = self.plugged_in == Some(true);
+        let lasted = |seconds: u64| now - self.changed_at >= Duration::from_secs(seconds);
+
+        rule.plugged_for_s.is_none_or(|s| plugged_in && lasted(s))
+            && rule
+                .unplugged_for_s
+                .is_none_or(|s| !plugged_in && lasted(s))
+            && rule.between.as_ref().is_none_or(|between| {
+                between
+                    .parse::<TimeWindow>()
+                    .is_ok_and(|window| window.contains(get_local_minute()))
+            })
+            && rule
+                .process
+                .as_ref()
+                .is_none_or(|name| is_process_running(name))
+    }
+}
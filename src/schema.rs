@@ -0,0 +1,181 @@
+use schemars::schema_for;
+use serde_json::Value;
+use std::{collections::HashMap, fmt::Write, fs, path::Path};
+
+use crate::config::{Config, Format};
+
+// JSON Schema of the config file. Fields deserialize to zero values that are
+// replaced when merging with `Config::default`, so the defaults derived from
+// the types would be wrong and the real ones are advertised instead.
+pub fn get_schema() -> Value {
+    let mut schema = serde_json::to_value(schema_for!(Config)).expect("serialize config schema");
+    remove_defaults(&mut schema);
+
+    let defaults = serde_json::to_value(Config::default()).expect("serialize default config");
+    if let (Some(properties), Value::Object(defaults)) =
+        (schema["properties"].as_object_mut(), defaults)
+    {
+        for (key, default) in defaults.into_iter().filter(|(_, d)| !d.is_null()) {
+            if let Some(property) = properties.get_mut(&key) {
+                property["default"] = default
+            }
+        }
+    }
+
+    schema
+}
+
+fn remove_defaults(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.remove("default");
+            map.values_mut().for_each(remove_defaults)
+        }
+        Value::Array(items) => items.iter_mut().for_each(remove_defaults),
+        _ => {}
+    }
+}
+
+// Follows `$ref`, which schemars wraps in `allOf` when the field is documented.
+fn resolve<'a>(root: &'a Value, schema: &'a Value) -> &'a Value {
+    let reference = schema["$ref"]
+        .as_str()
+        .or_else(|| schema["allOf"][0]["$ref"].as_str());
+
+    match reference.and_then(|r| r.strip_prefix("#/definitions/")) {
+        Some(name) => &root["definitions"][name],
+        None => schema,
+    }
+}
+
+// Documentation of every key by dotted path, tables included.
+fn get_descriptions(
+    root: &Value,
+    schema: &Value,
+    prefix: &str,
+    descriptions: &mut HashMap<String, String>,
+) {
+    let Some(properties) = schema["properties"].as_object() else {
+        return;
+    };
+
+    for (key, property) in properties {
+        let path = format!("{}{}", prefix, key);
+        if let Some(description) = property["description"].as_str() {
+            descriptions.insert(path.to_owned(), description.to_string());
+        }
+
+        get_descriptions(
+            root,
+            resolve(root, property),
+            &format!("{path}."),
+            descriptions,
+        )
+    }
+}
+
+// The default config as TOML, every key preceded by its documentation.
+pub fn render_default_config() -> String {
+    let schema = get_schema();
+    let defaults = serde_json::to_value(Config::default()).expect("serialize default config");
+
+    let mut descriptions = HashMap::new();
+    get_descriptions(&schema, &schema, "", &mut descriptions);
+
+    let mut out = String::from(
+        "# battery-notifier configuration, every value below is the default one.\n\
+         # https://github.com/luisnquin/battery-notifier\n",
+    );
+    render_toml(&mut out, &defaults, "", &|path, _| {
+        descriptions.get(path).cloned()
+    });
+
+    out
+}
+
+// Writes `values` as TOML, each key preceded by the comment `comment` returns
+// for its dotted path.
+pub fn render_toml(
+    out: &mut String,
+    values: &Value,
+    path: &str,
+    comment: &dyn Fn(&str, &Value) -> Option<String>,
+) {
+    let Some(values) = values.as_object() else {
+        return;
+    };
+
+    let get_path = |key: &str| {
+        if path.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+
+    let write_comment = |out: &mut String, path: &str, value: &Value| {
+        for line in comment(path, value).iter().flat_map(|c| c.lines()) {
+            let _ = writeln!(out, "# {}", line);
+        }
+    };
+
+    // TOML wants the plain keys of a table before its sub-tables.
+    for (key, value) in values.iter().filter(|(_, v)| !v.is_object()) {
+        out.push('\n');
+        write_comment(out, &get_path(key), value);
+
+        match toml::Value::try_from(value) {
+            Ok(value) => {
+                let _ = writeln!(out, "{} = {}", key, value);
+            }
+            Err(_) => {
+                let _ = writeln!(out, "# {} = \"\"", key);
+            }
+        }
+    }
+
+    for (key, value) in values.iter().filter(|(_, v)| v.is_object()) {
+        let path = get_path(key);
+
+        out.push('\n');
+        write_comment(out, &path, value);
+        let _ = writeln!(out, "[{}]", path);
+
+        render_toml(out, value, &path, comment);
+    }
+}
+
+// Writes the default config to `path` and returns the exit code.
+pub fn init_config(path: &str, force: bool) -> i32 {
+    if Path::new(path).exists() && !force {
+        eprintln!("{path} already exists, use --force to replace it");
+        return 1;
+    }
+
+    // Only TOML gets the comments, the other formats are plain serializations.
+    let content = match Format::from_path(path) {
+        Format::Toml => render_default_config(),
+        Format::Yaml => {
+            serde_yaml::to_string(&Config::default()).expect("serialize default config")
+        }
+        Format::Json => {
+            serde_json::to_string_pretty(&Config::default()).expect("serialize default config")
+        }
+    };
+
+    let result = Path::new(path)
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(path, content));
+
+    match result {
+        Ok(()) => {
+            println!("wrote the default config to {path}");
+            0
+        }
+        Err(error) => {
+            eprintln!("unable to write {path}: {error}");
+            1
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use log::warn;
+use serde::Deserialize;
+use std::{env, fs, io, os::unix::fs::DirBuilderExt, path::Path, process, time::Duration};
+
+use crate::{capture::Sample, clock::Clock};
+
+// A capacity is either fixed for the whole state or interpolated linearly
+// from `from` to `to` over the state duration.
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum CapacitySpec {
+    Fixed(u8),
+    Ramp { from: u8, to: u8 },
+}
+
+impl CapacitySpec {
+    fn at(&self, progress: f64) -> u8 {
+        match self {
+            CapacitySpec::Fixed(capacity) => *capacity,
+            CapacitySpec::Ramp { from, to } => {
+                let (from, to) = (*from as f64, *to as f64);
+                (from + (to - from) * progress.clamp(0.0, 1.0)).round() as u8
+            }
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct SimulatedBattery {
+    #[serde(default = "default_battery_name")]
+    name: String,
+    // "System" for laptop batteries, "Device" for peripherals.
+    #[serde(default)]
+    scope: Option<String>,
+    #[serde(default)]
+    model_name: Option<String>,
+    status: String,
+    capacity: CapacitySpec,
+    // µW, as reported by the kernel.
+    #[serde(default)]
+    power_now: Option<u64>,
+    // µWh, as reported by the kernel.
+    #[serde(default)]
+    energy_full: Option<u64>,
+}
+
+fn default_battery_name() -> String {
+    "BAT0".to_string()
+}
+
+#[derive(Clone, Deserialize)]
+struct DebugState {
+    // The top level fields describe the battery being monitored.
+    #[serde(flatten)]
+    battery: SimulatedBattery,
+    // Seconds, `seconds_between` is used when missing.
+    #[serde(default)]
+    duration: Option<f64>,
+    #[serde(default)]
+    adapter_online: Option<bool>,
+    // Any other battery present at the same time, including peripherals.
+    #[serde(default)]
+    batteries: Vec<SimulatedBattery>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct DebugSettings {
+    states: Vec<DebugState>,
+    #[serde(default = "default_seconds_between")]
+    seconds_between: f64,
+    // When disabled the simulation plays every state once and then ends.
+    #[serde(default = "default_loop", rename = "loop")]
+    looping: bool,
+}
+
+fn default_seconds_between() -> f64 {
+    5.0
+}
+
+fn default_loop() -> bool {
+    true
+}
+
+impl DebugSettings {
+    pub fn parse(debug_file_path: String) -> Self {
+        let content = fs::read_to_string(debug_file_path).expect("read file path");
+        let options: DebugSettings = serde_yaml::from_str(&content).expect("parse debug file");
+
+        if options.states.is_empty() {
+            panic!("debug file must declare at least one state");
+        }
+
+        let durations = options.states.iter().filter_map(|s| s.duration);
+        if options.seconds_between <= 0.0 || durations.into_iter().any(|d| d <= 0.0) {
+            panic!("state durations must be greater than zero");
+        }
+
+        options
+    }
+
+    // Plays a capture back as one state per sample, each lasting until the next
+    // sample was taken. The last one lasts as long as the gap before it.
+    pub fn from_capture(samples: Vec<Sample>) -> Self {
+        if samples.is_empty() {
+            panic!("the capture must contain at least one sample");
+        }
+
+        let gaps: Vec<f64> = samples
+            .windows(2)
+            .map(|w| w[1].at_ms.saturating_sub(w[0].at_ms) as f64 / 1000.0)
+            .collect();
+        let last_gap = gaps.last().copied().unwrap_or(default_seconds_between());
+
+        let states = samples
+            .into_iter()
+            .zip(gaps.into_iter().chain([last_gap]))
+            .map(|(sample, duration)| DebugState {
+                battery: SimulatedBattery {
+                    name: default_battery_name(),
+                    scope: None,
+                    model_name: None,
+                    status: sample.status,
+                    capacity: CapacitySpec::Fixed(sample.capacity),
+                    power_now: sample.power_now,
+                    energy_full: sample.energy_full,
+                },
+                duration: Some(duration),
+                adapter_online: sample.adapter_online,
+                batteries: Vec::new(),
+            })
+            .collect();
+
+        DebugSettings {
+            states,
+            seconds_between: default_seconds_between(),
+            looping: false,
+        }
+    }
+}
+
+// What a simulated battery reports at a given point of the simulation.
+#[derive(Clone)]
+pub struct BatteryReading {
+    pub name: String,
+    pub scope: Option<String>,
+    pub model_name: Option<String>,
+    pub status: String,
+    pub capacity: u8,
+    pub power_now: Option<u64>,
+    pub energy_now: Option<u64>,
+    pub energy_full: Option<u64>,
+}
+
+impl SimulatedBattery {
+    fn read(&self, progress: f64) -> BatteryReading {
+        let capacity = self.capacity.at(progress);
+
+        BatteryReading {
+            name: self.name.to_owned(),
+            scope: self.scope.to_owned(),
+            model_name: self.model_name.to_owned(),
+            status: self.status.to_owned(),
+            capacity,
+            power_now: self.power_now,
+            energy_now: self.energy_full.map(|full| full * capacity as u64 / 100),
+            energy_full: self.energy_full,
+        }
+    }
+}
+
+pub struct Snapshot {
+    // The monitored battery always comes first.
+    pub batteries: Vec<BatteryReading>,
+    pub adapter_online: Option<bool>,
+}
+
+// Creates the private directory simulations are written to. It must not
+// exist yet, so nothing but the simulated nodes is ever removed from it.
+pub fn create_simulation_root() -> io::Result<String> {
+    let root = env::temp_dir().join(format!("battery-notifier-{}", process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&root)?;
+
+    Ok(root.to_string_lossy().into_owned())
+}
+
+pub struct Debug {
+    settings: DebugSettings,
+    current_state: usize,
+    clock: Clock,
+    state_started_at: Duration,
+    finished: bool,
+    // Only scripts mentioning the adapter get an adapter node, the others keep
+    // exercising the status based plug detection.
+    has_adapter: bool,
+    written_root: Option<String>,
+    written_nodes: Vec<String>,
+}
+
+impl Debug {
+    pub fn new(settings: DebugSettings, clock: Clock) -> Self {
+        let has_adapter = settings.states.iter().any(|s| s.adapter_online.is_some());
+
+        Self {
+            settings,
+            current_state: 0,
+            state_started_at: clock.now(),
+            clock,
+            finished: false,
+            has_adapter,
+            written_root: None,
+            written_nodes: Vec::new(),
+        }
+    }
+
+    fn get_duration(&self, state: &DebugState) -> f64 {
+        state.duration.unwrap_or(self.settings.seconds_between)
+    }
+
+    fn get_elapsed_secs(&self, now: Duration) -> f64 {
+        now.saturating_sub(self.state_started_at).as_secs_f64()
+    }
+
+    // Moves through every state whose duration already elapsed, keeping the
+    // schedule anchored to when each state was due rather than when it was read.
+    pub fn tick(&mut self) {
+        let now = self.clock.now();
+
+        while !self.finished {
+            let duration = self.get_duration(&self.settings.states[self.current_state]);
+            if self.get_elapsed_secs(now) < duration {
+                break;
+            }
+
+            if self.current_state + 1 == self.settings.states.len() && !self.settings.looping {
+                self.finished = true;
+                break;
+            }
+
+            self.state_started_at += Duration::from_secs_f64(duration);
+            self.current_state = (self.current_state + 1) % self.settings.states.len();
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let state = &self.settings.states[self.current_state];
+        let duration = self.get_duration(state);
+        let progress = if duration > 0.0 {
+            self.get_elapsed_secs(self.clock.now()) / duration
+        } else {
+            1.0
+        };
+
+        let mut batteries = vec![state.battery.read(progress)];
+        batteries.extend(state.batteries.iter().map(|b| b.read(progress)));
+
+        Snapshot {
+            batteries,
+            adapter_online: state.adapter_online,
+        }
+    }
+
+    // Writes the current state as a power supply class under `root`, one
+    // directory per node, and removes the nodes that are gone since last time.
+    pub fn write_tree(&mut self, root: &str) -> io::Result<()> {
+        let snapshot = self.snapshot();
+        let mut nodes: Vec<(String, Vec<(&str, String)>)> = Vec::new();
+
+        for battery in &snapshot.batteries {
+            let mut attributes = vec![
+                ("type", "Battery".to_string()),
+                ("status", battery.status.to_owned()),
+                ("capacity", battery.capacity.to_string()),
+            ];
+
+            let optional = [
+                ("scope", battery.scope.to_owned()),
+                ("model_name", battery.model_name.to_owned()),
+                ("power_now", battery.power_now.map(|v| v.to_string())),
+                ("energy_now", battery.energy_now.map(|v| v.to_string())),
+                ("energy_full", battery.energy_full.map(|v| v.to_string())),
+            ];
+            for (name, value) in optional {
+                if let Some(value) = value {
+                    attributes.push((name, value))
+                }
+            }
+
+            nodes.push((battery.name.to_owned(), attributes));
+        }
+
+        if self.has_adapter {
+            let online = snapshot.adapter_online.unwrap_or_else(|| {
+                matches!(
+                    snapshot.batteries[0].status.as_str(),
+                    "Charging" | "Full" | "Not charging"
+                )
+            });
+
+            nodes.push((
+                "AC".to_string(),
+                vec![
+                    ("type", "Mains".to_string()),
+                    ("online", (online as u8).to_string()),
+                ],
+            ));
+        }
+
+        for (name, attributes) in &nodes {
+            let dir = Path::new(root).join(name);
+            fs::create_dir_all(&dir)?;
+
+            for (attribute, value) in attributes {
+                fs::write(dir.join(attribute), format!("{}\n", value))?;
+            }
+        }
+
+        let names: Vec<String> = nodes.into_iter().map(|(name, _)| name).collect();
+        for stale in self.written_nodes.iter().filter(|n| !names.contains(n)) {
+            fs::remove_dir_all(Path::new(root).join(stale))?;
+        }
+
+        self.written_root = Some(root.to_string());
+        self.written_nodes = names;
+
+        Ok(())
+    }
+
+    // Removes every node written by the simulation, and the root as well when
+    // nothing else lives there.
+    pub fn remove_tree(&mut self) {
+        let Some(root) = self.written_root.take() else {
+            return;
+        };
+
+        for name in self.written_nodes.drain(..) {
+            if let Err(error) = fs::remove_dir_all(Path::new(&root).join(&name)) {
+                warn!("unable to remove simulated node {}: {}", name, error)
+            }
+        }
+
+        let _ = fs::remove_dir(&root);
+    }
+}
@@ -0,0 +1,286 @@
+use log::{debug, error, info, warn};
+use notify_rust::NotificationHandle;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::OpenOptions,
+    io::Write,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    broadcast::{Broadcaster, Message},
+    config::{Config, SinkKind},
+    dry_run::{Action, DryRun},
+    event::Event,
+    hooks::spawn_command,
+    notify::{send_desktop_notification, Urgency},
+};
+
+pub trait Notifier {
+    fn notify(&mut self, event: &Event) -> Result<(), Box<dyn Error>>;
+
+    // Withdraws whatever the sink is still showing, e.g. once the charger is plugged in.
+    fn dismiss(&mut self) {}
+}
+
+// Keeps the last notification of every device on screen, so a low mouse
+// battery doesn't replace the warning about the system battery.
+pub struct DesktopSink {
+    icon_path: Option<String>,
+    last_handles: HashMap<Option<String>, NotificationHandle>,
+}
+
+impl DesktopSink {
+    pub fn new(icon_path: Option<String>) -> Self {
+        Self {
+            icon_path,
+            last_handles: HashMap::new(),
+        }
+    }
+}
+
+impl Notifier for DesktopSink {
+    fn notify(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        if let Some(handle) = self.last_handles.remove(&event.device) {
+            handle.close()
+        }
+
+        let handle = send_desktop_notification(
+            event.urgency,
+            event.title.as_str(),
+            event.content.as_str(),
+            self.icon_path.to_owned(),
+        )?;
+        self.last_handles.insert(event.device.to_owned(), handle);
+
+        Ok(())
+    }
+
+    // Only the system battery notification is withdrawn, plugging in the laptop
+    // doesn't charge the mouse.
+    fn dismiss(&mut self) {
+        if let Some(handle) = self.last_handles.remove(&None) {
+            handle.close()
+        }
+    }
+}
+
+pub struct TerminalSink {
+    wall: bool,
+}
+
+impl TerminalSink {
+    pub fn new(wall: bool) -> Self {
+        Self { wall }
+    }
+}
+
+impl Notifier for TerminalSink {
+    fn notify(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        let message = format!("{}\n{}", event.title, event.content);
+
+        // The bell only makes sense when we were started from a terminal, daemons
+        // under systemd have no controlling TTY and rely on `wall` instead.
+        match OpenOptions::new().write(true).open("/dev/tty") {
+            Ok(mut tty) => writeln!(tty, "\x07{}", message)?,
+            Err(error) => debug!("no controlling terminal, skipping bell: {}", error),
+        }
+
+        if self.wall {
+            let mut child = Command::new("wall")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .spawn()?;
+
+            child
+                .stdin
+                .take()
+                .expect("wall stdin is piped")
+                .write_all(message.as_bytes())?;
+
+            thread::spawn(move || {
+                if let Err(error) = child.wait() {
+                    error!("error waiting for wall: {}", error)
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+pub struct LogSink;
+
+impl Notifier for LogSink {
+    fn notify(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        let content = event.content.replace('\n', " ");
+
+        match event.urgency {
+            Urgency::CRITICAL => error!("{}: {}", event.title, content),
+            Urgency::NORMAL => warn!("{}: {}", event.title, content),
+            Urgency::LOW => info!("{}: {}", event.title, content),
+        }
+
+        Ok(())
+    }
+}
+
+pub struct WebhookSink {
+    url: String,
+    timeout: Duration,
+}
+
+impl WebhookSink {
+    pub fn new(url: String, timeout_ms: u64) -> Self {
+        Self {
+            url,
+            timeout: Duration::from_millis(timeout_ms),
+        }
+    }
+}
+
+impl Notifier for WebhookSink {
+    fn notify(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        let body = serde_json::to_string(event)?;
+        let url = self.url.to_owned();
+        let timeout = self.timeout;
+
+        // A slow endpoint must never delay the next battery check.
+        thread::spawn(move || {
+            let result = ureq::post(&url)
+                .timeout(timeout)
+                .set("Content-Type", "application/json")
+                .send_string(&body);
+
+            match result {
+                Ok(response) => debug!("webhook answered with status {}", response.status()),
+                Err(error) => error!("error sending webhook to {}: {}", url, error),
+            }
+        });
+
+        Ok(())
+    }
+}
+
+pub struct CommandSink {
+    run: String,
+    timeout: Duration,
+}
+
+impl CommandSink {
+    pub fn new(run: String, timeout_ms: u64) -> Self {
+        Self {
+            run,
+            timeout: Duration::from_millis(timeout_ms),
+        }
+    }
+}
+
+impl Notifier for CommandSink {
+    fn notify(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        spawn_command(&self.run, event.env_vars(), self.timeout)?;
+
+        Ok(())
+    }
+}
+
+pub struct Notifiers {
+    sinks: Vec<(SinkKind, Box<dyn Notifier>)>,
+    dry_run: Option<DryRun>,
+    // Agents of the user sessions get every event, whatever sinks it's routed to.
+    broadcaster: Option<Broadcaster>,
+}
+
+impl Notifiers {
+    pub fn from_config(config: &Config, dry_run: Option<DryRun>) -> Self {
+        if dry_run.is_some() {
+            return Self {
+                sinks: Vec::new(),
+                dry_run,
+                broadcaster: None,
+            };
+        }
+
+        let sinks = config
+            .used_sinks()
+            .into_iter()
+            .map(|kind| {
+                let sink: Box<dyn Notifier> = match kind {
+                    SinkKind::Desktop => Box::new(DesktopSink::new(config.icon_path.to_owned())),
+                    SinkKind::Terminal => Box::new(TerminalSink::new(config.sinks.terminal.wall)),
+                    SinkKind::Log => Box::new(LogSink),
+                    SinkKind::Webhook => Box::new(WebhookSink::new(
+                        config.sinks.webhook.url.to_owned(),
+                        config.sinks.webhook.timeout_ms,
+                    )),
+                    SinkKind::Command => Box::new(CommandSink::new(
+                        config.sinks.command.run.to_owned(),
+                        config.sinks.command.timeout_ms,
+                    )),
+                };
+
+                (kind, sink)
+            })
+            .collect();
+
+        Self {
+            sinks,
+            dry_run: None,
+            broadcaster: None,
+        }
+    }
+
+    // The system service has no session to show anything in, the agents render
+    // the desktop and terminal notifications in theirs.
+    pub fn with_broadcaster(mut self, broadcaster: Option<Broadcaster>) -> Self {
+        if self.dry_run.is_none() && broadcaster.is_some() {
+            self.sinks
+                .retain(|(kind, _)| !matches!(kind, SinkKind::Desktop | SinkKind::Terminal));
+            self.broadcaster = broadcaster;
+        }
+
+        self
+    }
+
+    pub fn notify(&mut self, kinds: &[SinkKind], event: &Event) {
+        if let Some(dry_run) = &self.dry_run {
+            return dry_run.print(Action::Notification {
+                sinks: kinds,
+                event,
+            });
+        }
+
+        if let Some(broadcaster) = &self.broadcaster {
+            broadcaster.send(&Message::Notification {
+                event: event.clone(),
+            });
+        }
+
+        for (kind, sink) in self.sinks.iter_mut() {
+            if !kinds.contains(kind) {
+                continue;
+            }
+
+            if let Err(error) = sink.notify(event) {
+                error!("error sending {:?} notification: {}", kind, error)
+            }
+        }
+    }
+
+    pub fn dismiss(&mut self) {
+        if let Some(dry_run) = &self.dry_run {
+            return dry_run.print(Action::Dismiss);
+        }
+
+        if let Some(broadcaster) = &self.broadcaster {
+            broadcaster.send(&Message::Dismiss);
+        }
+
+        for (_, sink) in self.sinks.iter_mut() {
+            sink.dismiss()
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use log::{info, warn};
+use std::{
+    mem,
+    sync::mpsc::{self, Receiver, Sender},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::Clock,
+    logind::{Bus, Logind},
+};
+
+// Time CLOCK_BOOTTIME must gain over CLOCK_MONOTONIC between two checks to be
+// taken for a suspension rather than scheduling noise.
+const MIN_SLEEP: Duration = Duration::from_secs(2);
+
+// How late the logind signal may follow a resume noticed from the clocks.
+const RESUME_DEBOUNCE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SleepEvent {
+    Suspending,
+    Resumed,
+    // Sent from within the daemon when something waits on the loop.
+    Wake,
+}
+
+// Time spent suspended since boot, which CLOCK_MONOTONIC doesn't count.
+fn get_suspended_time() -> Duration {
+    let read = |clock: libc::clockid_t| unsafe {
+        let mut time: libc::timespec = mem::zeroed();
+        libc::clock_gettime(clock, &mut time);
+
+        Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+    };
+
+    read(libc::CLOCK_BOOTTIME).saturating_sub(read(libc::CLOCK_MONOTONIC))
+}
+
+// Tells the loop when the system suspends and resumes, from logind when it's
+// reachable and from the clocks otherwise.
+pub struct SleepMonitor {
+    sender: Sender<SleepEvent>,
+    receiver: Receiver<SleepEvent>,
+    logind: Option<Logind>,
+    suspended_time: Duration,
+    // When a resume was last noticed from the clocks.
+    jumped_at: Option<Instant>,
+}
+
+impl SleepMonitor {
+    pub fn new(bus: Bus) -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        let logind = Logind::connect(bus).and_then(|logind| {
+            logind.watch_sleep(sender.clone())?;
+            Ok(logind)
+        });
+
+        let logind = match logind {
+            Ok(logind) => {
+                info!("watching logind for suspend and resume");
+                Some(logind)
+            }
+            Err(error) => {
+                warn!(
+                    "unable to watch logind, resumes are detected from clock jumps only: {}",
+                    error
+                );
+                None
+            }
+        };
+
+        Self {
+            sender,
+            receiver,
+            logind,
+            suspended_time: get_suspended_time(),
+            jumped_at: None,
+        }
+    }
+
+    pub fn get_logind(&self) -> Option<&Logind> {
+        self.logind.as_ref()
+    }
+
+    // Lets other threads cut a sleep short with `SleepEvent::Wake`.
+    pub fn get_waker(&self) -> Sender<SleepEvent> {
+        self.sender.clone()
+    }
+
+    // Sleeps like `Clock::sleep`, returning early when the system is about to
+    // suspend or resumes, or when woken up.
+    pub fn sleep(&mut self, clock: &Clock, duration: Duration) -> Option<SleepEvent> {
+        let event = clock.sleep_or_recv(duration, &self.receiver);
+
+        let suspended_time = get_suspended_time();
+        let jumped = suspended_time.saturating_sub(self.suspended_time) >= MIN_SLEEP;
+        self.suspended_time = suspended_time;
+
+        let resumed = match event {
+            Some(SleepEvent::Suspending) => return event,
+            // The clocks may have told about this resume already, logind
+            // sends its signal a bit later.
+            Some(SleepEvent::Resumed) => self
+                .jumped_at
+                .take()
+                .is_none_or(|at| at.elapsed() >= RESUME_DEBOUNCE),
+            // A resume noticed from the clocks matters more.
+            Some(SleepEvent::Wake) if !jumped => return event,
+            _ if jumped => {
+                self.jumped_at = Some(Instant::now());
+                true
+            }
+            _ => false,
+        };
+
+        resumed.then_some(SleepEvent::Resumed)
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    env,
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+pub const APP_NAME: &str = "battery-notifier";
+
+// The base directories of the XDG spec the program keeps files in.
+// https://specifications.freedesktop.org/basedir-spec/latest/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BaseDir {
+    Config,
+    State,
+    Runtime,
+}
+
+impl BaseDir {
+    pub const ALL: [BaseDir; 3] = [BaseDir::Config, BaseDir::State, BaseDir::Runtime];
+
+    fn get_variable(self) -> &'static str {
+        match self {
+            BaseDir::Config => "XDG_CONFIG_HOME",
+            BaseDir::State => "XDG_STATE_HOME",
+            BaseDir::Runtime => "XDG_RUNTIME_DIR",
+        }
+    }
+
+    // Relative to HOME, the runtime dir has no such default.
+    fn get_fallback(self) -> Option<&'static str> {
+        match self {
+            BaseDir::Config => Some(".config"),
+            BaseDir::State => Some(".local/state"),
+            BaseDir::Runtime => None,
+        }
+    }
+}
+
+impl fmt::Display for BaseDir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BaseDir::Config => write!(f, "config"),
+            BaseDir::State => write!(f, "state"),
+            BaseDir::Runtime => write!(f, "runtime"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct XdgError {
+    kind: BaseDir,
+}
+
+impl fmt::Display for XdgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind.get_fallback() {
+            Some(_) => write!(
+                f,
+                "unable to locate the {} directory, neither {} nor HOME is set",
+                self.kind,
+                self.kind.get_variable()
+            ),
+            None => write!(
+                f,
+                "unable to locate the runtime directory, {} is not set",
+                self.kind.get_variable()
+            ),
+        }
+    }
+}
+
+impl Error for XdgError {}
+
+// The spec asks to ignore relative paths, an empty variable counts as unset.
+fn get_env_dir(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+}
+
+// HOME is unset in system units, which is why nothing here unwraps it.
+pub fn get_base_dir(kind: BaseDir) -> Result<PathBuf, XdgError> {
+    if let Some(dir) = get_env_dir(kind.get_variable()) {
+        return Ok(dir);
+    }
+
+    if kind == BaseDir::Runtime {
+        // What pam_systemd would have set XDG_RUNTIME_DIR to.
+        let dir = Path::new("/run/user").join(unsafe { libc::getuid() }.to_string());
+        return if dir.is_dir() {
+            Ok(dir)
+        } else {
+            Err(XdgError { kind })
+        };
+    }
+
+    match (get_env_dir("HOME"), kind.get_fallback()) {
+        (Some(home), Some(fallback)) => Ok(home.join(fallback)),
+        _ => Err(XdgError { kind }),
+    }
+}
+
+// The directory of the program inside the base directory `kind`.
+pub fn get_app_dir(kind: BaseDir) -> Result<PathBuf, XdgError> {
+    get_base_dir(kind).map(|dir| dir.join(APP_NAME))
+}
+
+// The runtime directory of the system service, systemd passes it when the
+// unit sets `RuntimeDirectory=`.
+pub fn get_system_runtime_dir() -> PathBuf {
+    get_env_dir("RUNTIME_DIRECTORY").unwrap_or_else(|| Path::new("/run").join(APP_NAME))
+}
+
+// The system-wide config directories of the program, the most important first.
+pub fn get_config_dirs() -> Vec<PathBuf> {
+    let dirs: Vec<PathBuf> = env::var("XDG_CONFIG_DIRS")
+        .unwrap_or_default()
+        .split(':')
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .collect();
+
+    if dirs.is_empty() {
+        vec![Path::new("/etc/xdg").join(APP_NAME)]
+    } else {
+        dirs.into_iter().map(|dir| dir.join(APP_NAME)).collect()
+    }
+}
@@ -0,0 +1,198 @@
+// Runs `check-config` against config files written to a temp dir.
+
+use std::{fs, process::Command};
+
+fn check_config(content: &str, args: &[&str]) -> (i32, Vec<String>) {
+    check_config_file("config.toml", content, args)
+}
+
+fn check_config_file(name: &str, content: &str, args: &[&str]) -> (i32, Vec<String>) {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let config = dir.path().join(name);
+    fs::write(&config, content).expect("write config");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_battery-notifier"))
+        .arg("--config-file")
+        .arg(&config)
+        .arg("check-config")
+        .args(args)
+        .output()
+        .expect("run battery-notifier");
+
+    let prefix = format!("{}", config.display());
+    let lines = String::from_utf8(output.stdout)
+        .expect("utf-8 output")
+        .lines()
+        .map(|l| l.replacen(&prefix, name, 1))
+        .collect();
+
+    (output.status.code().expect("exit code"), lines)
+}
+
+#[test]
+fn accepts_valid_configs() {
+    let (code, lines) = check_config("sound = false\n\n[warn]\nthreshold = 20\n", &[]);
+
+    assert_eq!(code, 0);
+    assert_eq!(lines, ["config.toml: the config is valid"]);
+}
+
+#[test]
+fn reports_every_problem_with_its_position() {
+    let (code, lines) = check_config(
+        r#"icon_path = "/does/not/exist.png"
+
+[reminder]
+threshold = 10
+tittle = "Low"
+
+[warn]
+threshold = 150
+
+[sinks]
+enabled = ["webhook"]
+"#,
+        &[],
+    );
+
+    assert_eq!(code, 1);
+    assert_eq!(
+        lines,
+        [
+            "config.toml:1:13: error: icon '/does/not/exist.png' does not exist",
+            "config.toml:4:13: error: reminder threshold must be higher than warn threshold",
+            "config.toml:5:1: warning: unknown key 'reminder.tittle' is ignored",
+            "config.toml:8:13: error: 'warn.threshold' must be between 1 and 100, got 150",
+            "config.toml: error: webhook sink is enabled but 'sinks.webhook.url' is empty",
+            "config.toml: 4 error(s), 1 warning(s)",
+        ]
+    );
+}
+
+#[test]
+fn skips_missing_files_without_fs_checks() {
+    let config = "icon_path = \"/home/someone/.local/share/icons/battery.png\"\n";
+
+    assert_eq!(check_config(config, &[]).0, 1);
+    assert_eq!(check_config(config, &["--no-fs-checks"]).0, 0);
+}
+
+#[test]
+fn fails_on_warnings_when_strict() {
+    let config = "[warn]\ntreshold = 20\n";
+
+    assert_eq!(check_config(config, &[]).0, 0);
+    assert_eq!(check_config(config, &["--strict"]).0, 1);
+}
+
+#[test]
+fn reports_syntax_errors() {
+    let (code, lines) = check_config("[warn]\nthreshold = \n", &[]);
+
+    assert_eq!(code, 1);
+    assert!(
+        lines[0].starts_with("config.toml:2:13: error: "),
+        "{:?}",
+        lines
+    );
+}
+
+#[test]
+fn generates_a_valid_default_config() {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let config = dir.path().join("battery-notifier").join("config.toml");
+
+    let init_config = || {
+        Command::new(env!("CARGO_BIN_EXE_battery-notifier"))
+            .arg("--config-file")
+            .arg(&config)
+            .arg("init-config")
+            .status()
+            .expect("run battery-notifier")
+    };
+
+    assert!(init_config().success());
+    // An existing config is never replaced without --force.
+    assert!(!init_config().success());
+
+    let content = fs::read_to_string(&config).expect("read generated config");
+    assert_eq!(check_config(&content, &["--strict"]).0, 0);
+}
+
+#[test]
+fn checks_yaml_and_json_configs() {
+    let (code, lines) = check_config_file(
+        "config.yaml",
+        "warn:\n  threshold: 20\n  tittle: Low\n",
+        &[],
+    );
+    assert_eq!(code, 0);
+    assert_eq!(
+        lines,
+        [
+            "config.yaml: warning: unknown key 'warn.tittle' is ignored",
+            "config.yaml: 0 error(s), 1 warning(s)",
+        ]
+    );
+
+    let (code, lines) = check_config_file("config.json", r#"{"warn": {"threshold": "low"}}"#, &[]);
+    assert_eq!(code, 1);
+    assert_eq!(
+        lines[0],
+        r#"config.json:1:28: error: invalid type: string "low", expected u8"#
+    );
+}
+
+#[test]
+fn checks_profiles() {
+    let (code, lines) = check_config(
+        r#"[profiles.plane]
+sound = true
+volume = 3
+
+[profiles.plane.warn]
+threshold = 40
+
+[[profile_rules]]
+profile = "desk"
+between = "9:00-25:00"
+
+[[profile_rules]]
+profile = "plane"
+proces = "steam"
+"#,
+        &[],
+    );
+
+    assert_eq!(code, 1);
+    assert_eq!(
+        lines,
+        [
+            "config.toml:3:1: warning: unknown key 'profiles.plane.volume' is ignored",
+            "config.toml:6:13: error: profile 'plane' reminder threshold must be higher than its warn threshold",
+            "config.toml:14:1: warning: unknown key 'profile_rules[1].proces' is ignored",
+            "config.toml: error: profile rule selects the unknown profile 'desk'",
+            "config.toml: error: invalid time window '9:00-25:00', expected HH:MM-HH:MM",
+            "config.toml: 3 error(s), 2 warning(s)",
+        ]
+    );
+}
+
+#[test]
+fn checks_the_sinks_of_profiles() {
+    let (code, lines) = check_config(
+        r#"[profiles.plane.warn]
+sinks = ["webhook"]
+"#,
+        &[],
+    );
+
+    assert_eq!(code, 1);
+    assert_eq!(
+        lines,
+        [
+            "config.toml:2:9: error: profile 'plane' sends warn to the webhook sink but 'sinks.webhook.url' is empty",
+            "config.toml: 1 error(s), 0 warning(s)",
+        ]
+    );
+}
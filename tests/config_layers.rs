@@ -0,0 +1,182 @@
+// Runs `config dump` against layers written to a temp dir.
+
+use std::{fs, process::Command};
+
+fn dump(dir: &std::path::Path, env: &[(&str, &str)], args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_battery-notifier"))
+        .arg("--config-file")
+        .arg(dir.join("config.toml"))
+        .args(args)
+        .envs(env.iter().copied())
+        .output()
+        .expect("run battery-notifier");
+
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout)
+        .expect("utf-8 output")
+        .replace(&format!("{}", dir.display()), "$DIR")
+}
+
+#[test]
+fn later_layers_win_key_by_key() {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    fs::create_dir(dir.path().join("config.d")).expect("create drop-in dir");
+    fs::write(
+        dir.path().join("config.toml"),
+        "sound = false\n\n[warn]\nthreshold = 20\n\n[threat]\nthreshold = 5\n",
+    )
+    .expect("write config");
+    fs::write(
+        dir.path().join("config.d").join("10-warn.yaml"),
+        "warn:\n  threshold: 25\nreminder:\n  threshold: 40\n",
+    )
+    .expect("write drop-in");
+
+    let output = dump(
+        dir.path(),
+        &[
+            ("BATTERY_NOTIFIER_WARN__THRESHOLD", "24"),
+            ("BATTERY_NOTIFIER_THREAT__THRESHOLD", "not a number"),
+        ],
+        &[
+            "--reminder-threshold",
+            "35",
+            "--set",
+            "reminder.threshold=38",
+            "config",
+            "dump",
+        ],
+    );
+
+    assert_eq!(
+        output,
+        "# from $DIR/config.toml
+sound = false
+
+[reminder]
+
+# from --set reminder.threshold
+threshold = 38
+
+[threat]
+
+# from $DIR/config.toml
+threshold = 5
+
+[warn]
+
+# from env BATTERY_NOTIFIER_WARN__THRESHOLD
+threshold = 24
+"
+    );
+}
+
+#[test]
+fn shows_where_effective_values_come_from() {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    fs::write(dir.path().join("config.toml"), "[warn]\nthreshold = 20\n").expect("write config");
+
+    let output = dump(
+        dir.path(),
+        &[],
+        &["--interval-ms", "1000", "config", "dump", "--effective"],
+    );
+
+    for expected in [
+        "# from --interval-ms\ninterval_ms = 1000\n",
+        "# from default\nsound = true\n",
+        "# from $DIR/config.toml\nthreshold = 20\n",
+    ] {
+        assert!(output.contains(expected), "{expected:?} not in {output}");
+    }
+}
+
+#[test]
+fn keeps_zero_where_it_turns_a_check_off() {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    fs::write(
+        dir.path().join("config.toml"),
+        "[not_charging]\nmin_adapter_watts = 0\n",
+    )
+    .expect("write config");
+
+    let output = dump(dir.path(), &[], &["config", "dump", "--effective"]);
+
+    assert!(
+        output.contains("# from $DIR/config.toml\nmin_adapter_watts = 0\n"),
+        "{output}"
+    );
+}
+
+#[test]
+fn rejects_unknown_keys_on_the_command_line() {
+    let output = Command::new(env!("CARGO_BIN_EXE_battery-notifier"))
+        .args(["--set", "warn.treshold=20", "config", "dump"])
+        .output()
+        .expect("run battery-notifier");
+
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn follows_the_xdg_base_directories() {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let user = dir.path().join("user").join("battery-notifier");
+    let system = dir.path().join("system").join("battery-notifier");
+    fs::create_dir_all(&user).expect("create user config dir");
+    fs::create_dir_all(&system).expect("create system config dir");
+    fs::write(
+        system.join("config.toml"),
+        "sound = false\n\n[warn]\nthreshold = 20\n",
+    )
+    .expect("write system config");
+    // Any format is found, not only TOML.
+    fs::write(user.join("config.yaml"), "warn:\n  threshold: 25\n").expect("write user config");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_battery-notifier"))
+        .args(["config", "dump"])
+        .env_clear()
+        .env("XDG_CONFIG_HOME", dir.path().join("user"))
+        .env("XDG_CONFIG_DIRS", dir.path().join("system"))
+        .output()
+        .expect("run battery-notifier");
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout)
+            .expect("utf-8 output")
+            .replace(&format!("{}", dir.path().display()), "$DIR"),
+        "# from $DIR/system/battery-notifier/config.toml
+sound = false
+
+[warn]
+
+# from $DIR/user/battery-notifier/config.yaml
+threshold = 25
+"
+    );
+}
+
+#[test]
+fn does_without_home() {
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_battery-notifier"))
+            .args(args)
+            .env_clear()
+            .env("XDG_CONFIG_DIRS", "/nonexistent")
+            .output()
+            .expect("run battery-notifier")
+    };
+
+    let output = run(&["--warn-threshold", "20", "config", "dump"]);
+    assert!(output.status.success(), "{:?}", output);
+
+    // The user file is needed here, which is an error rather than a panic.
+    let output = run(&["check-config"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).expect("utf-8 output"),
+        "error: unable to locate the config directory, neither XDG_CONFIG_HOME nor HOME is set, \
+         pass --config-file\n"
+    );
+}
@@ -0,0 +1,1416 @@
+// Drives the whole notify loop against a fake power supply class. Sinks are
+// replaced by a command appending every event to a file the tests read back.
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    os::{
+        fd::{FromRawFd, IntoRawFd},
+        unix::net::UnixStream,
+    },
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tempfile::TempDir;
+
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Polls `condition` until it holds, failing with `describe` after `timeout`.
+fn wait_within(
+    timeout: Duration,
+    mut condition: impl FnMut() -> bool,
+    describe: impl Fn() -> String,
+) {
+    let deadline = Instant::now() + timeout;
+
+    while !condition() {
+        assert!(Instant::now() < deadline, "{}", describe());
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn wait_until(condition: impl FnMut() -> bool, describe: impl Fn() -> String) {
+    wait_within(WAIT_TIMEOUT, condition, describe)
+}
+
+struct Daemon {
+    child: Child,
+    dir: TempDir,
+}
+
+impl Daemon {
+    fn start(extra_config: &str, setup: impl FnOnce(&Path)) -> Self {
+        Self::start_with_args(extra_config, &[], setup)
+    }
+
+    fn start_with_args(extra_config: &str, args: &[&str], setup: impl FnOnce(&Path)) -> Self {
+        Self::start_with_env(extra_config, args, &[], setup)
+    }
+
+    fn start_with_env(
+        extra_config: &str,
+        args: &[&str],
+        env: &[(&str, &str)],
+        setup: impl FnOnce(&Path),
+    ) -> Self {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let root = dir.path().join("power_supply");
+        fs::create_dir(&root).expect("create power supply root");
+        setup(&root);
+
+        let events = dir.path().join("events");
+        let config = format!(
+            r#"
+interval_ms = 50
+sound = false
+
+[sinks]
+enabled = ["command"]
+
+[sinks.command]
+run = 'echo "$BATTERY_EVENT $BATTERY_CAPACITY $BATTERY_DEVICE" >> {events}'
+
+{extra_config}
+"#,
+            events = events.display(),
+        );
+
+        let config_path = dir.path().join("config.toml");
+        fs::write(&config_path, config).expect("write config");
+
+        let child = Command::new(env!("CARGO_BIN_EXE_battery-notifier"))
+            .arg("--config-file")
+            .arg(&config_path)
+            .arg("--power-supply-root")
+            .arg(&root)
+            .args(args)
+            // Keeps the control socket of every test apart.
+            .env("XDG_RUNTIME_DIR", dir.path())
+            .envs(env.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("start battery-notifier");
+
+        Daemon { child, dir }
+    }
+
+    fn root(&self) -> PathBuf {
+        self.dir.path().join("power_supply")
+    }
+
+    fn events(&self) -> Vec<String> {
+        fs::read_to_string(self.dir.path().join("events"))
+            .unwrap_or_default()
+            .lines()
+            .map(|l| l.trim().to_string())
+            .collect()
+    }
+
+    fn wait_for_event(&self, expected: &str) {
+        wait_until(
+            || self.events().iter().any(|e| e == expected),
+            || {
+                format!(
+                    "event {:?} never arrived, got {:?}",
+                    expected,
+                    self.events()
+                )
+            },
+        )
+    }
+
+    fn wait_for_events(&self, count: usize) {
+        wait_until(
+            || self.events().len() >= count,
+            || format!("got {:?}", self.events()),
+        )
+    }
+
+    // Runs `ctl` against the daemon once it listens, returns the exit code and output.
+    fn ctl(&self, args: &[&str]) -> (i32, String) {
+        let deadline = Instant::now() + WAIT_TIMEOUT;
+
+        loop {
+            let output = Command::new(env!("CARGO_BIN_EXE_battery-notifier"))
+                .arg("ctl")
+                .args(args)
+                .env("XDG_RUNTIME_DIR", self.dir.path())
+                .output()
+                .expect("run battery-notifier ctl");
+
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !stderr.contains("unable to reach the daemon") || Instant::now() > deadline {
+                let stdout = String::from_utf8(output.stdout).expect("utf-8 output");
+                let code = output.status.code().expect("exit code");
+
+                return (code, format!("{}{}", stdout, stderr).trim().to_string());
+            }
+
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    fn wait_for_exit(&mut self, timeout: Duration) {
+        wait_within(
+            timeout,
+            || self.child.try_wait().expect("poll child").is_some(),
+            || "the daemon is still running".to_string(),
+        )
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// A private bus standing for the system one, None when dbus-daemon isn't installed.
+struct MockBus {
+    child: Child,
+    address: String,
+}
+
+impl MockBus {
+    fn start() -> Option<Self> {
+        let mut child = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+
+        let mut address = String::new();
+        let stdout = child.stdout.take().expect("dbus-daemon stdout");
+        BufReader::new(stdout)
+            .read_line(&mut address)
+            .expect("read bus address");
+
+        Some(MockBus {
+            child,
+            address: address.trim().to_string(),
+        })
+    }
+
+    // Owns the logind name so the daemon listens to the signals sent from it.
+    fn logind(&self) -> zbus::blocking::Connection {
+        zbus::blocking::ConnectionBuilder::address(self.address.as_str())
+            .and_then(|builder| builder.name("org.freedesktop.login1"))
+            .and_then(|builder| builder.build())
+            .expect("start mock logind")
+    }
+
+    // Same as `logind`, answering the manager methods from `manager`.
+    fn logind_serving(&self, manager: MockManager) -> zbus::blocking::Connection {
+        zbus::blocking::ConnectionBuilder::address(self.address.as_str())
+            .and_then(|builder| builder.name("org.freedesktop.login1"))
+            .and_then(|builder| builder.serve_at("/org/freedesktop/login1", manager))
+            .and_then(|builder| builder.build())
+            .expect("start mock logind")
+    }
+}
+
+impl Drop for MockBus {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Records the calls made to it. Inhibitor locks are handed out as one end of
+// a socket pair, the other end reads EOF once the daemon closes its lock.
+#[derive(Clone, Default)]
+struct MockManager {
+    calls: Arc<Mutex<Vec<String>>>,
+    locks: Arc<Mutex<Vec<UnixStream>>>,
+    lid_closed: Arc<Mutex<bool>>,
+}
+
+impl MockManager {
+    fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn wait_for_calls(&self, count: usize) {
+        wait_until(
+            || self.calls().len() >= count,
+            || format!("got {:?}", self.calls()),
+        )
+    }
+
+    fn wait_for_release(&self, index: usize) {
+        let mut lock = self.locks.lock().unwrap()[index]
+            .try_clone()
+            .expect("clone lock");
+        lock.set_read_timeout(Some(WAIT_TIMEOUT))
+            .expect("set read timeout");
+
+        let read = lock.read(&mut [0; 1]).expect("the lock is never released");
+        assert_eq!(read, 0);
+    }
+}
+
+#[zbus::dbus_interface(name = "org.freedesktop.login1.Manager")]
+impl MockManager {
+    fn inhibit(&self, what: &str, _who: &str, _why: &str, mode: &str) -> zbus::zvariant::OwnedFd {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(format!("inhibit {} {}", what, mode));
+
+        let (ours, theirs) = UnixStream::pair().expect("create lock");
+        self.locks.lock().unwrap().push(ours);
+
+        unsafe { zbus::zvariant::OwnedFd::from_raw_fd(theirs.into_raw_fd()) }
+    }
+
+    fn hibernate(&self, _interactive: bool) {
+        self.calls.lock().unwrap().push("hibernate".to_string());
+    }
+
+    #[dbus_interface(property)]
+    fn handle_lid_switch(&self) -> String {
+        "suspend".to_string()
+    }
+
+    #[dbus_interface(property)]
+    fn lid_closed(&self) -> bool {
+        *self.lid_closed.lock().unwrap()
+    }
+
+    #[dbus_interface(property)]
+    fn docked(&self) -> bool {
+        false
+    }
+}
+
+// Serves the power-profiles-daemon interface, only the active profile is
+// implemented.
+#[derive(Clone)]
+struct MockPowerProfiles {
+    active: Arc<Mutex<String>>,
+}
+
+impl MockPowerProfiles {
+    fn start(bus: &MockBus, active: &str) -> (Self, zbus::blocking::Connection) {
+        let mock = MockPowerProfiles {
+            active: Arc::new(Mutex::new(active.to_string())),
+        };
+
+        let connection = zbus::blocking::ConnectionBuilder::address(bus.address.as_str())
+            .and_then(|builder| builder.name("net.hadess.PowerProfiles"))
+            .and_then(|builder| builder.serve_at("/net/hadess/PowerProfiles", mock.clone()))
+            .and_then(|builder| builder.build())
+            .expect("start mock power-profiles-daemon");
+
+        (mock, connection)
+    }
+
+    fn wait_for_profile(&self, expected: &str) {
+        wait_until(
+            || *self.active.lock().unwrap() == expected,
+            || {
+                format!(
+                    "the active profile is still {:?}",
+                    self.active.lock().unwrap()
+                )
+            },
+        )
+    }
+}
+
+#[zbus::dbus_interface(name = "net.hadess.PowerProfiles")]
+impl MockPowerProfiles {
+    #[dbus_interface(property)]
+    fn active_profile(&self) -> String {
+        self.active.lock().unwrap().clone()
+    }
+
+    #[dbus_interface(property)]
+    fn set_active_profile(&mut self, profile: String) {
+        *self.active.lock().unwrap() = profile;
+    }
+}
+
+fn prepare_for_sleep(logind: &zbus::blocking::Connection, start: bool) {
+    logind
+        .emit_signal(
+            None::<&str>,
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+            "PrepareForSleep",
+            &start,
+        )
+        .expect("emit PrepareForSleep");
+}
+
+fn write_node(root: &Path, name: &str, attributes: &[(&str, &str)]) {
+    let dir = root.join(name);
+    fs::create_dir_all(&dir).expect("create node");
+
+    for (attribute, value) in attributes {
+        fs::write(dir.join(attribute), format!("{}\n", value)).expect("write attribute");
+    }
+}
+
+#[test]
+fn notifies_every_level_once() {
+    let daemon = Daemon::start("", |root| {
+        write_node(
+            root,
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("status", "Discharging"),
+                ("capacity", "50"),
+            ],
+        );
+    });
+
+    for (capacity, expected) in [("25", "reminder 25"), ("12", "warn 12"), ("4", "threat 4")] {
+        write_node(&daemon.root(), "BAT0", &[("capacity", capacity)]);
+        daemon.wait_for_event(expected);
+    }
+
+    write_node(&daemon.root(), "BAT0", &[("capacity", "3")]);
+    thread::sleep(Duration::from_millis(300));
+
+    assert_eq!(daemon.events(), ["reminder 25", "warn 12", "threat 4"]);
+}
+
+#[test]
+fn notifies_unplug_when_the_adapter_goes_offline() {
+    let daemon = Daemon::start("", |root| {
+        write_node(
+            root,
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("status", "Charging"),
+                ("capacity", "80"),
+            ],
+        );
+        write_node(root, "AC", &[("type", "Mains"), ("online", "1")]);
+    });
+
+    thread::sleep(Duration::from_millis(300));
+    write_node(&daemon.root(), "AC", &[("online", "0")]);
+    write_node(&daemon.root(), "BAT0", &[("status", "Discharging")]);
+
+    daemon.wait_for_event("unplug 80");
+}
+
+#[test]
+fn notifies_levels_while_draining_with_the_adapter_online() {
+    // A charger too weak for the load.
+    let daemon = Daemon::start("", |root| {
+        write_node(
+            root,
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("status", "Discharging"),
+                ("capacity", "4"),
+            ],
+        );
+        write_node(root, "AC", &[("type", "Mains"), ("online", "1")]);
+    });
+
+    daemon.wait_for_event("threat 4");
+    thread::sleep(Duration::from_millis(300));
+
+    assert!(
+        !daemon.events().iter().any(|e| e.starts_with("unplug")),
+        "got {:?}",
+        daemon.events()
+    );
+}
+
+#[test]
+fn notifies_low_peripherals() {
+    let daemon = Daemon::start(
+        r#"
+[peripherals]
+enabled = true
+"#,
+        |root| {
+            write_node(
+                root,
+                "BAT0",
+                &[
+                    ("type", "Battery"),
+                    ("status", "Discharging"),
+                    ("capacity", "90"),
+                ],
+            );
+            write_node(
+                root,
+                "hid-mouse-battery",
+                &[
+                    ("type", "Battery"),
+                    ("scope", "Device"),
+                    ("model_name", "Mouse"),
+                    ("status", "Discharging"),
+                    ("capacity", "40"),
+                ],
+            );
+        },
+    );
+
+    write_node(&daemon.root(), "hid-mouse-battery", &[("capacity", "10")]);
+    daemon.wait_for_event("peripheral 10 hid-mouse-battery");
+}
+
+#[test]
+fn plays_long_scenarios_with_a_virtual_clock() {
+    let scenario = tempfile::NamedTempFile::new().expect("create scenario");
+    fs::write(
+        scenario.path(),
+        r#"
+loop: false
+states:
+  - status: Discharging
+    capacity: { from: 40, to: 0 }
+    duration: 600
+"#,
+    )
+    .expect("write scenario");
+
+    let debug_file = scenario.path().to_str().expect("utf-8 path");
+    let mut daemon = Daemon::start_with_args(
+        "",
+        &["--speed", "virtual", "--debug-file", debug_file],
+        |_| {},
+    );
+
+    // Ten minutes of simulated discharge go by without waiting for them.
+    daemon.wait_for_exit(Duration::from_secs(30));
+    daemon.wait_for_event("threat 5");
+
+    assert_eq!(daemon.events(), ["reminder 30", "warn 15", "threat 5"]);
+}
+
+#[test]
+fn prints_actions_instead_of_firing_them_in_dry_run() {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let scenario = dir.path().join("scenario.yaml");
+    fs::write(
+        &scenario,
+        r#"
+loop: false
+states:
+  - status: Discharging
+    capacity: { from: 20, to: 10 }
+    duration: 60
+"#,
+    )
+    .expect("write scenario");
+
+    let config = dir.path().join("config.toml");
+    fs::write(
+        &config,
+        "interval_ms = 1000\n\n[charge_control]\nend_threshold = 80\n",
+    )
+    .expect("write config");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_battery-notifier"))
+        .arg("--dry-run")
+        .args(["--speed", "virtual"])
+        .arg("--config-file")
+        .arg(&config)
+        .arg("--debug-file")
+        .arg(&scenario)
+        .arg("--power-supply-root")
+        .arg(dir.path().join("power_supply"))
+        .stderr(Stdio::null())
+        .output()
+        .expect("run battery-notifier");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout)
+            .expect("utf-8 output")
+            .lines()
+            .collect::<Vec<_>>(),
+        [
+            r#"{"at_ms":0,"type":"charge_thresholds","start":null,"end":80}"#,
+            r#"{"at_ms":0,"type":"notification","sinks":["desktop"],"event":{"kind":"reminder","level":"reminder","urgency":"low","capacity":20,"status":"Discharging","title":"Battery somewhat low","content":"Charge: 20%","device":null}}"#,
+            r#"{"at_ms":0,"type":"sound","sound":"reminder"}"#,
+            r#"{"at_ms":28000,"type":"notification","sinks":["desktop"],"event":{"kind":"warn","level":"warn","urgency":"normal","capacity":15,"status":"Discharging","title":"Battery low","content":"Charge: 15%.\nPlease connect your laptop","device":null}}"#,
+            r#"{"at_ms":28000,"type":"sound","sound":"warn"}"#,
+        ]
+    );
+}
+
+#[test]
+fn replays_captures() {
+    let capture = tempfile::NamedTempFile::new().expect("create capture");
+    fs::write(
+        capture.path(),
+        r#"{"at_ms":0,"capacity":31,"status":"Discharging"}
+{"at_ms":60000,"capacity":30,"status":"Discharging"}
+{"at_ms":120000,"capacity":29,"status":"Charging"}
+{"at_ms":180000,"capacity":14,"status":"Discharging"}
+"#,
+    )
+    .expect("write capture");
+
+    let replay = capture.path().to_str().expect("utf-8 path");
+    let mut daemon =
+        Daemon::start_with_args("", &["--speed", "virtual", "--replay", replay], |_| {});
+
+    daemon.wait_for_exit(Duration::from_secs(30));
+    daemon.wait_for_event("warn 14");
+
+    // Unplug and warn fire on the same reading, their commands may finish in any order.
+    let mut events = daemon.events();
+    events.sort();
+    assert_eq!(events, ["reminder 30", "unplug 14", "warn 14"]);
+}
+
+#[test]
+fn selects_profiles_by_how_long_the_laptop_runs_on_battery() {
+    let capture = tempfile::NamedTempFile::new().expect("create capture");
+    fs::write(
+        capture.path(),
+        r#"{"at_ms":0,"capacity":90,"status":"Charging"}
+{"at_ms":60000,"capacity":85,"status":"Discharging"}
+{"at_ms":120000,"capacity":75,"status":"Discharging"}
+{"at_ms":780000,"capacity":74,"status":"Discharging"}
+"#,
+    )
+    .expect("write capture");
+
+    let replay = capture.path().to_str().expect("utf-8 path");
+    let mut daemon = Daemon::start_with_args(
+        r#"
+[profiles.plane.reminder]
+threshold = 80
+
+[[profile_rules]]
+profile = "plane"
+unplugged_for_s = 600
+"#,
+        &["--speed", "virtual", "--replay", replay],
+        |_| {},
+    );
+
+    daemon.wait_for_exit(Duration::from_secs(30));
+    daemon.wait_for_event("reminder 75");
+
+    // 75% is below the reminder of the profile once unplugged for ten minutes.
+    assert_eq!(daemon.events(), ["unplug 85", "reminder 75"]);
+}
+
+#[test]
+fn switches_profiles_with_ctl() {
+    let daemon = Daemon::start(
+        r#"
+[profiles.plane]
+repeat_s = 1
+
+[profiles.plane.reminder]
+threshold = 90
+"#,
+        |root| {
+            write_node(
+                root,
+                "BAT0",
+                &[
+                    ("type", "Battery"),
+                    ("status", "Discharging"),
+                    ("capacity", "80"),
+                ],
+            );
+        },
+    );
+
+    assert_eq!(daemon.ctl(&["profile"]), (0, "no profile is active".into()));
+
+    // A client sending nothing doesn't hold the others back.
+    let _silent = UnixStream::connect(
+        daemon
+            .dir
+            .path()
+            .join("battery-notifier")
+            .join("control.sock"),
+    )
+    .expect("connect to the control socket");
+    let started = Instant::now();
+    assert_eq!(daemon.ctl(&["profile"]), (0, "no profile is active".into()));
+    assert!(
+        started.elapsed() < Duration::from_secs(2),
+        "{:?}",
+        started.elapsed()
+    );
+
+    assert_eq!(
+        daemon.ctl(&["profile", "desk"]),
+        (1, "error: unknown profile 'desk'".into())
+    );
+    assert_eq!(
+        daemon.ctl(&["profile", "plane"]),
+        (0, "plane is active until `ctl profile auto`".into())
+    );
+    assert_eq!(
+        daemon.ctl(&["profile"]),
+        (0, "plane (selected with ctl)".into())
+    );
+
+    daemon.wait_for_event("reminder 80");
+
+    // The profile notifies the level again every second.
+    daemon.wait_for_events(2);
+
+    assert_eq!(
+        daemon.ctl(&["profile", "auto"]),
+        (0, "profiles are selected by the rules again".into())
+    );
+    thread::sleep(Duration::from_millis(300));
+    let events = daemon.events();
+    thread::sleep(Duration::from_millis(1500));
+
+    assert!(events.iter().all(|e| e == "reminder 80"), "{:?}", events);
+    assert_eq!(daemon.events(), events);
+}
+
+#[test]
+fn routes_profile_levels_to_sinks_the_base_config_leaves_out() {
+    let webhook = std::net::TcpListener::bind("127.0.0.1:0").expect("bind webhook");
+    let daemon = Daemon::start(
+        &format!(
+            r#"
+[sinks.webhook]
+url = "http://{}/"
+
+[profiles.plane.warn]
+sinks = ["webhook"]
+"#,
+            webhook.local_addr().expect("webhook address")
+        ),
+        |root| {
+            write_node(
+                root,
+                "BAT0",
+                &[
+                    ("type", "Battery"),
+                    ("status", "Discharging"),
+                    ("capacity", "50"),
+                ],
+            );
+        },
+    );
+    assert_eq!(
+        daemon.ctl(&["profile", "plane"]),
+        (0, "plane is active until `ctl profile auto`".into())
+    );
+
+    write_node(&daemon.root(), "BAT0", &[("capacity", "10")]);
+
+    webhook.set_nonblocking(true).expect("set nonblocking");
+    let mut accepted = None;
+    wait_until(
+        || {
+            accepted = webhook.accept().ok();
+            accepted.is_some()
+        },
+        || "the webhook was never called".to_string(),
+    );
+    let (mut stream, _) = accepted.expect("accepted webhook request");
+    stream.set_nonblocking(false).expect("set blocking");
+    stream
+        .set_read_timeout(Some(WAIT_TIMEOUT))
+        .expect("set read timeout");
+    let mut request = Vec::new();
+    let mut buffer = [0; 4096];
+    while !String::from_utf8_lossy(&request).contains(r#""capacity":10"#) {
+        let read = stream.read(&mut buffer).expect("read webhook request");
+        assert!(read > 0, "{}", String::from_utf8_lossy(&request));
+        request.extend_from_slice(&buffer[..read]);
+    }
+    let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+
+    assert!(String::from_utf8_lossy(&request).contains(r#""kind":"warn""#));
+    assert_eq!(daemon.events(), Vec::<String>::new());
+}
+
+#[test]
+fn sets_charge_thresholds_from_the_config_and_with_ctl() {
+    let daemon = Daemon::start("[charge_control]\nend_threshold = 80", |root| {
+        write_node(
+            root,
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("status", "Not charging"),
+                ("capacity", "80"),
+                ("charge_control_start_threshold", "0"),
+                ("charge_control_end_threshold", "100"),
+            ],
+        );
+    });
+    let read_threshold = |name: &str| {
+        fs::read_to_string(daemon.root().join("BAT0").join(name))
+            .expect("read threshold")
+            .trim()
+            .to_string()
+    };
+
+    assert_eq!(
+        daemon.ctl(&["charge-limit"]),
+        (0, "charging stops at 80%".into())
+    );
+    assert_eq!(
+        daemon.ctl(&["charge-limit", "--start", "90", "85"]),
+        (
+            1,
+            "error: the start threshold (90) must be lower than the end threshold (85)".into()
+        )
+    );
+    assert_eq!(
+        daemon.ctl(&["charge-limit", "--start", "60", "85"]),
+        (0, "charging starts below 60% and stops at 85%".into())
+    );
+    assert_eq!(read_threshold("charge_control_start_threshold"), "60");
+    assert_eq!(read_threshold("charge_control_end_threshold"), "85");
+}
+
+#[test]
+fn tolerates_a_battery_draining_above_the_start_threshold() {
+    let daemon = Daemon::start("[not_charging]\ngrace_s = 1", |root| {
+        write_node(root, "AC", &[("type", "Mains"), ("online", "1")]);
+        write_node(
+            root,
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("status", "Not charging"),
+                ("capacity", "80"),
+                ("charge_control_start_threshold", "75"),
+                ("charge_control_end_threshold", "80"),
+            ],
+        );
+    });
+    thread::sleep(Duration::from_millis(300));
+
+    // Held between the thresholds, the drop is not the charger's fault.
+    write_node(&daemon.root(), "BAT0", &[("capacity", "76")]);
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(daemon.events(), Vec::<String>::new());
+
+    write_node(&daemon.root(), "BAT0", &[("capacity", "73")]);
+    daemon.wait_for_event("not_charging 73");
+}
+
+#[test]
+fn survives_capacities_out_of_range() {
+    let daemon = Daemon::start("", |root| {
+        write_node(root, "AC", &[("type", "Mains"), ("online", "1")]);
+        write_node(
+            root,
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("status", "Not charging"),
+                ("capacity", "255"),
+            ],
+        );
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    assert_eq!(daemon.ctl(&["profile"]), (0, "no profile is active".into()));
+}
+
+#[test]
+fn polls_less_often_far_from_thresholds() {
+    let capture = tempfile::NamedTempFile::new().expect("create capture");
+    fs::write(
+        capture.path(),
+        r#"{"at_ms":0,"capacity":90,"status":"Discharging"}
+{"at_ms":3600000,"capacity":31,"status":"Discharging"}
+{"at_ms":3660000,"capacity":30,"status":"Discharging"}
+{"at_ms":3720000,"capacity":29,"status":"Discharging"}
+"#,
+    )
+    .expect("write capture");
+    let record = tempfile::NamedTempFile::new().expect("create record");
+
+    let replay = capture.path().to_str().expect("utf-8 path");
+    let mut daemon = Daemon::start_with_args(
+        r#"
+[polling]
+adaptive = true
+min_interval_ms = 1000
+max_interval_ms = 60000
+"#,
+        &[
+            "--speed",
+            "virtual",
+            "--replay",
+            replay,
+            "--record",
+            record.path().to_str().expect("utf-8 path"),
+        ],
+        |_| {},
+    );
+
+    daemon.wait_for_exit(Duration::from_secs(30));
+    daemon.wait_for_event("reminder 30");
+
+    // Every 50ms would be tens of thousands of checks over the two hours.
+    let samples: Vec<serde_json::Value> = fs::read_to_string(record.path())
+        .expect("read record")
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("parse sample"))
+        .collect();
+    assert!(samples.len() < 150, "{} checks", samples.len());
+
+    // One percent above the reminder, the reading is checked again within the
+    // minute the capacity takes to drop at the observed drain.
+    let reached_at = samples
+        .iter()
+        .find(|sample| sample["capacity"] == 30)
+        .and_then(|sample| sample["at_ms"].as_u64())
+        .expect("a sample at 30%");
+    assert!(reached_at - 3660000 < 31000, "noticed at {}", reached_at);
+}
+
+#[test]
+fn polls_a_draining_battery_with_the_adapter_online() {
+    let capture = tempfile::NamedTempFile::new().expect("create capture");
+    fs::write(
+        capture.path(),
+        r#"{"at_ms":0,"capacity":90,"status":"Discharging","adapter_online":true}
+{"at_ms":3600000,"capacity":31,"status":"Discharging","adapter_online":true}
+{"at_ms":3660000,"capacity":30,"status":"Discharging","adapter_online":true}
+{"at_ms":3720000,"capacity":29,"status":"Discharging","adapter_online":true}
+"#,
+    )
+    .expect("write capture");
+
+    let replay = capture.path().to_str().expect("utf-8 path");
+    let mut daemon = Daemon::start_with_args(
+        r#"
+[polling]
+adaptive = true
+min_interval_ms = 1000
+max_interval_ms = 600000
+"#,
+        &["--speed", "virtual", "--replay", replay],
+        |_| {},
+    );
+
+    // Polling as if plugged in, the next check would come after the capture ends.
+    daemon.wait_for_exit(Duration::from_secs(30));
+    daemon.wait_for_event("reminder 30");
+}
+
+#[test]
+fn notifies_again_right_after_resume() {
+    let Some(bus) = MockBus::start() else {
+        eprintln!("dbus-daemon is not installed, skipping");
+        return;
+    };
+    let logind = bus.logind();
+
+    // Nothing but a resume wakes the loop up within the test.
+    let daemon = Daemon::start_with_env(
+        "",
+        &["--interval-ms", "600000", "--logind-bus", "session"],
+        &[("DBUS_SESSION_BUS_ADDRESS", &bus.address)],
+        |root| {
+            write_node(
+                root,
+                "BAT0",
+                &[
+                    ("type", "Battery"),
+                    ("status", "Discharging"),
+                    ("capacity", "25"),
+                ],
+            );
+        },
+    );
+    daemon.wait_for_event("reminder 25");
+
+    // The level the battery is still in is notified again.
+    prepare_for_sleep(&logind, true);
+    prepare_for_sleep(&logind, false);
+    daemon.wait_for_events(2);
+
+    // The battery drained while the system was suspended.
+    prepare_for_sleep(&logind, true);
+    write_node(&daemon.root(), "BAT0", &[("capacity", "4")]);
+    prepare_for_sleep(&logind, false);
+    daemon.wait_for_event("threat 4");
+
+    assert_eq!(daemon.events(), ["reminder 25", "reminder 25", "threat 4"]);
+}
+
+#[test]
+fn warns_before_suspending_at_the_threat_level() {
+    let Some(bus) = MockBus::start() else {
+        eprintln!("dbus-daemon is not installed, skipping");
+        return;
+    };
+    let manager = MockManager::default();
+    let logind = bus.logind_serving(manager.clone());
+
+    let daemon = Daemon::start_with_env(
+        "[sleep]\ninhibit = true\nwarn_ms = 100",
+        &["--interval-ms", "600000", "--logind-bus", "session"],
+        &[("DBUS_SESSION_BUS_ADDRESS", &bus.address)],
+        |root| {
+            write_node(
+                root,
+                "BAT0",
+                &[
+                    ("type", "Battery"),
+                    ("status", "Discharging"),
+                    ("capacity", "4"),
+                ],
+            );
+        },
+    );
+    daemon.wait_for_event("threat 4");
+    manager.wait_for_calls(1);
+
+    // The suspend waits for the warning, then the lock is taken again.
+    prepare_for_sleep(&logind, true);
+    daemon.wait_for_event("suspend 4");
+    manager.wait_for_release(0);
+
+    prepare_for_sleep(&logind, false);
+    manager.wait_for_calls(2);
+
+    assert_eq!(
+        manager.calls(),
+        ["inhibit sleep delay", "inhibit sleep delay"]
+    );
+}
+
+#[test]
+fn releases_the_sleep_lock_when_the_battery_cant_be_read() {
+    let Some(bus) = MockBus::start() else {
+        eprintln!("dbus-daemon is not installed, skipping");
+        return;
+    };
+    let manager = MockManager::default();
+    let logind = bus.logind_serving(manager.clone());
+
+    // No capacity, every check fails.
+    let _daemon = Daemon::start_with_env(
+        "[sleep]\ninhibit = true",
+        &["--interval-ms", "600000", "--logind-bus", "session"],
+        &[("DBUS_SESSION_BUS_ADDRESS", &bus.address)],
+        |root| {
+            write_node(
+                root,
+                "BAT0",
+                &[("type", "Battery"), ("status", "Discharging")],
+            )
+        },
+    );
+    manager.wait_for_calls(1);
+
+    prepare_for_sleep(&logind, true);
+    manager.wait_for_release(0);
+
+    prepare_for_sleep(&logind, false);
+    manager.wait_for_calls(2);
+}
+
+#[test]
+fn hibernates_when_the_lid_closes_at_the_threat_level() {
+    let Some(bus) = MockBus::start() else {
+        eprintln!("dbus-daemon is not installed, skipping");
+        return;
+    };
+    let manager = MockManager::default();
+
+    let _logind = bus.logind_serving(manager.clone());
+
+    let daemon = Daemon::start_with_env(
+        "[sleep]\ncritical_action = \"hibernate\"",
+        &["--logind-bus", "session"],
+        &[("DBUS_SESSION_BUS_ADDRESS", &bus.address)],
+        |root| {
+            write_node(
+                root,
+                "BAT0",
+                &[
+                    ("type", "Battery"),
+                    ("status", "Discharging"),
+                    ("capacity", "4"),
+                ],
+            );
+        },
+    );
+    daemon.wait_for_event("threat 4");
+    manager.wait_for_calls(1);
+
+    // logind leaves the lid to the daemon, which hibernates when it closes.
+    *manager.lid_closed.lock().unwrap() = true;
+    manager.wait_for_calls(2);
+
+    // Plugging the adapter in gives the lid back to logind.
+    write_node(&daemon.root(), "BAT0", &[("status", "Charging")]);
+    manager.wait_for_release(0);
+
+    assert_eq!(
+        manager.calls(),
+        ["inhibit handle-lid-switch block", "hibernate"]
+    );
+    assert_eq!(daemon.events(), ["threat 4"]);
+}
+
+#[test]
+fn switches_power_profiles_and_restores_them_across_restarts() {
+    let Some(bus) = MockBus::start() else {
+        eprintln!("dbus-daemon is not installed, skipping");
+        return;
+    };
+    let (power_profiles, _connection) = MockPowerProfiles::start(&bus, "balanced");
+    let state = tempfile::tempdir().expect("create state dir");
+    let state_file = state.path().join("battery-notifier/power-profile.json");
+
+    let config = "[power_profiles]\nenabled = true\nreminder = \"power-saver\"";
+    let args = ["--power-profiles-bus", "session"];
+    let env = [
+        ("DBUS_SESSION_BUS_ADDRESS", bus.address.as_str()),
+        ("XDG_STATE_HOME", state.path().to_str().expect("utf-8 path")),
+    ];
+    let start = |status: &str| {
+        Daemon::start_with_env(config, &args, &env, |root| {
+            write_node(
+                root,
+                "BAT0",
+                &[("type", "Battery"), ("status", status), ("capacity", "25")],
+            );
+        })
+    };
+
+    let daemon = start("Discharging");
+    daemon.wait_for_event("reminder 25");
+    power_profiles.wait_for_profile("power-saver");
+    drop(daemon);
+    assert!(state_file.exists());
+
+    // The profile to restore outlives the daemon.
+    let _daemon = start("Charging");
+    power_profiles.wait_for_profile("balanced");
+    wait_until(
+        || !state_file.exists(),
+        || "the state file is still there".to_string(),
+    );
+}
+
+#[test]
+fn dims_the_screen_and_brings_it_back_on_plug_in() {
+    let backlight = tempfile::tempdir().expect("create backlight class");
+    // Firmware interfaces win over raw registers.
+    write_node(
+        backlight.path(),
+        "acpi_video0",
+        &[
+            ("type", "firmware"),
+            ("brightness", "1000"),
+            ("max_brightness", "1000"),
+        ],
+    );
+    write_node(
+        backlight.path(),
+        "intel_backlight",
+        &[
+            ("type", "raw"),
+            ("brightness", "1000"),
+            ("max_brightness", "1000"),
+        ],
+    );
+    let read_brightness = |name: &str| {
+        fs::read_to_string(backlight.path().join(name).join("brightness"))
+            .expect("read brightness")
+            .trim()
+            .to_string()
+    };
+    let wait_for_brightness = |expected: &str| {
+        wait_until(
+            || read_brightness("acpi_video0") == expected,
+            || format!("the brightness is still {}", read_brightness("acpi_video0")),
+        )
+    };
+
+    // Given through the environment, which isn't mistaken for a config key.
+    let daemon = Daemon::start_with_env(
+        "[backlight]\nenabled = true",
+        &[],
+        &[(
+            "BATTERY_NOTIFIER_BACKLIGHT_ROOT",
+            backlight.path().to_str().expect("utf-8 path"),
+        )],
+        |root| {
+            write_node(
+                root,
+                "BAT0",
+                &[
+                    ("type", "Battery"),
+                    ("status", "Discharging"),
+                    ("capacity", "10"),
+                ],
+            );
+        },
+    );
+    daemon.wait_for_event("warn 10");
+    wait_for_brightness("700");
+
+    // Levels dim from the brightness before the first one.
+    write_node(&daemon.root(), "BAT0", &[("capacity", "4")]);
+    daemon.wait_for_event("threat 4");
+    wait_for_brightness("500");
+
+    write_node(&daemon.root(), "BAT0", &[("status", "Charging")]);
+    wait_for_brightness("1000");
+    assert_eq!(read_brightness("intel_backlight"), "1000");
+}
+
+#[test]
+fn agents_render_the_events_of_the_system_service() {
+    let runtime = tempfile::tempdir().expect("create runtime dir");
+    let socket = runtime.path().join("events.sock");
+    let env = [(
+        "RUNTIME_DIRECTORY",
+        runtime.path().to_str().expect("utf-8 path"),
+    )];
+    let start_system = |capacity: &str| {
+        let daemon = Daemon::start_with_env("", &["--system"], &env, |root| {
+            write_node(
+                root,
+                "BAT0",
+                &[
+                    ("type", "Battery"),
+                    ("status", "Discharging"),
+                    ("capacity", capacity),
+                ],
+            );
+        });
+
+        wait_until(
+            || socket.exists(),
+            || "the events socket never showed up".to_string(),
+        );
+
+        daemon
+    };
+
+    let system = start_system("25");
+    system.wait_for_event("reminder 25");
+
+    // Agents connecting late, such as on login, get the current notification.
+    let agent = Daemon::start_with_args(
+        "",
+        &[
+            "agent",
+            "--events-socket",
+            socket.to_str().expect("utf-8 path"),
+        ],
+        |_| {},
+    );
+    agent.wait_for_event("reminder 25");
+
+    // The agent comes back once the service restarts.
+    drop(system);
+    let _system = start_system("10");
+    agent.wait_for_event("warn 10");
+
+    assert_eq!(agent.events(), ["reminder 25", "warn 10"]);
+}
+
+#[test]
+fn caps_the_agents_of_each_user() {
+    let runtime = tempfile::tempdir().expect("create runtime dir");
+    let socket = runtime.path().join("events.sock");
+    let daemon = Daemon::start_with_env(
+        "",
+        &["--system"],
+        &[(
+            "RUNTIME_DIRECTORY",
+            runtime.path().to_str().expect("utf-8 path"),
+        )],
+        |root| {
+            write_node(
+                root,
+                "BAT0",
+                &[
+                    ("type", "Battery"),
+                    ("status", "Discharging"),
+                    ("capacity", "25"),
+                ],
+            );
+        },
+    );
+    daemon.wait_for_event("reminder 25");
+
+    // Accepted agents get the current notification, refused ones are hung up on.
+    let connect = || {
+        let stream = UnixStream::connect(&socket).expect("connect to the events socket");
+        stream
+            .set_read_timeout(Some(WAIT_TIMEOUT))
+            .expect("set read timeout");
+        let mut line = String::new();
+        BufReader::new(stream.try_clone().expect("clone stream"))
+            .read_line(&mut line)
+            .expect("read from the events socket");
+        (stream, line)
+    };
+
+    let mut agents: Vec<UnixStream> = (0..4)
+        .map(|_| {
+            let (stream, line) = connect();
+            assert!(line.contains(r#""kind":"reminder""#), "{:?}", line);
+            stream
+        })
+        .collect();
+    assert_eq!(connect().1, "");
+
+    // The slot of an agent that went away is given to the next one.
+    agents.pop();
+    assert!(connect().1.contains(r#""kind":"reminder""#));
+}
+
+#[test]
+fn exposes_the_loop_state_over_dbus() {
+    let Some(bus) = MockBus::start() else {
+        eprintln!("dbus-daemon is not installed, skipping");
+        return;
+    };
+
+    let daemon = Daemon::start_with_env(
+        r#"
+[hooks]
+on_warn = 'echo "$BATTERY_HOOK $BATTERY_CAPACITY" >> "$XDG_RUNTIME_DIR/events"'
+"#,
+        &[],
+        &[("DBUS_SESSION_BUS_ADDRESS", &bus.address)],
+        |root| {
+            write_node(
+                root,
+                "BAT0",
+                &[
+                    ("type", "Battery"),
+                    ("status", "Discharging"),
+                    ("capacity", "25"),
+                    ("charge_control_end_threshold", "100"),
+                ],
+            );
+        },
+    );
+    daemon.wait_for_event("reminder 25");
+
+    let connection = zbus::blocking::ConnectionBuilder::address(bus.address.as_str())
+        .and_then(|builder| builder.build())
+        .expect("connect to the mock bus");
+    let proxy = zbus::blocking::Proxy::new(
+        &connection,
+        "io.github.luisnquin.BatteryNotifier",
+        "/io/github/luisnquin/BatteryNotifier",
+        "io.github.luisnquin.BatteryNotifier",
+    )
+    .expect("create proxy");
+
+    let get_property = |name: &str| {
+        proxy
+            .get_property::<zbus::zvariant::OwnedValue>(name)
+            .map(|value| format!("{:?}", value))
+    };
+    let wait_for_property = |name: &str, expected: &str| {
+        wait_until(
+            || get_property(name).is_ok_and(|value| value.contains(expected)),
+            || format!("{} is still {:?}", name, get_property(name)),
+        )
+    };
+    wait_for_property("Capacity", "25");
+    wait_for_property("Level", "reminder");
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let signals = proxy
+        .receive_signal("LevelEntered")
+        .expect("subscribe to LevelEntered");
+    thread::spawn(move || {
+        for signal in signals {
+            let _ = sender.send(signal.body::<(String, String, u8)>().expect("signal body"));
+        }
+    });
+
+    // The warn level is entered but neither notified nor hooked while snoozed.
+    let () = proxy.call("Snooze", &(600u32,)).expect("call Snooze");
+    wait_for_property("Snoozed", "true");
+    write_node(&daemon.root(), "BAT0", &[("capacity", "10")]);
+
+    let entered = receiver
+        .recv_timeout(WAIT_TIMEOUT)
+        .expect("LevelEntered never arrived");
+    assert_eq!(entered, ("warn".to_string(), "reminder".to_string(), 10));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(daemon.events(), ["reminder 25"]);
+
+    // It's notified once the snooze is over, and only then.
+    let () = proxy.call("Snooze", &(0u32,)).expect("call Snooze");
+    daemon.wait_for_event("warn 10");
+    daemon.wait_for_event("on_warn 10");
+    assert!(
+        receiver.try_recv().is_err(),
+        "the warn level was entered again"
+    );
+
+    let () = proxy
+        .call("TestNotification", &())
+        .expect("call TestNotification");
+    daemon.wait_for_event("test 10");
+
+    // A config with problems is refused and the running one is kept.
+    let config_path = daemon.dir.path().join("config.toml");
+    let config = fs::read_to_string(&config_path).expect("read config");
+    fs::write(
+        &config_path,
+        format!("{}\n[reminder]\nthreshold = 10\n", config),
+    )
+    .expect("write config");
+    let reload: zbus::Result<()> = proxy.call("Reload", &());
+    assert!(reload.is_err(), "an invalid config was reloaded");
+
+    // So is one that can't be parsed, rather than replaced by the defaults.
+    fs::write(&config_path, format!("{}\n[reminder\n", config)).expect("write config");
+    let reload: zbus::Result<()> = proxy.call("Reload", &());
+    let error = reload.expect_err("an unparsable config was reloaded");
+    assert!(error.to_string().contains("unable to parse"), "{}", error);
+
+    // Settings read at startup follow a config reloaded fine.
+    fs::write(
+        &config_path,
+        format!("{}\n[charge_control]\nend_threshold = 80\n", config),
+    )
+    .expect("write config");
+    let () = proxy.call("Reload", &()).expect("call Reload");
+    let read_threshold = || {
+        fs::read_to_string(
+            daemon
+                .root()
+                .join("BAT0")
+                .join("charge_control_end_threshold"),
+        )
+        .expect("read threshold")
+    };
+    wait_until(
+        || read_threshold().trim() == "80",
+        || format!("the end threshold is still {}", read_threshold()),
+    );
+
+    let mut events = daemon.events();
+    events.sort();
+    assert_eq!(events, ["on_warn 10", "reminder 25", "test 10", "warn 10"]);
+}